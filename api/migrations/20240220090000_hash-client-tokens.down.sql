@@ -0,0 +1,11 @@
+-- Add down migration script here
+ALTER TABLE clients
+DROP COLUMN previous_token_expires_at;
+ALTER TABLE clients
+DROP COLUMN previous_token_hash;
+ALTER TABLE clients
+DROP COLUMN token_hash;
+ALTER TABLE clients
+ADD token VARCHAR(128) NOT NULL DEFAULT '';
+CREATE INDEX idx_token
+ON clients (token);
@@ -0,0 +1,14 @@
+-- Add up migration script here
+-- Plaintext tokens can't be hashed without the server key, so existing
+-- clients need their token rotated after this migration.
+DROP INDEX IF EXISTS idx_token;
+ALTER TABLE clients
+DROP COLUMN token;
+ALTER TABLE clients
+ADD token_hash TEXT NOT NULL DEFAULT '';
+ALTER TABLE clients
+ALTER COLUMN token_hash DROP DEFAULT;
+ALTER TABLE clients
+ADD previous_token_hash TEXT;
+ALTER TABLE clients
+ADD previous_token_expires_at TIMESTAMPTZ;
@@ -0,0 +1,9 @@
+-- Add down migration script here
+ALTER TABLE clients
+DROP COLUMN scopes;
+ALTER TABLE clients
+DROP COLUMN last_used_at;
+ALTER TABLE clients
+DROP COLUMN revoked_at;
+ALTER TABLE clients
+DROP COLUMN expires_at;
@@ -0,0 +1,12 @@
+-- Add up migration script here
+ALTER TABLE clients
+ADD expires_at TIMESTAMPTZ;
+ALTER TABLE clients
+ADD revoked_at TIMESTAMPTZ;
+ALTER TABLE clients
+ADD last_used_at TIMESTAMPTZ;
+-- existing clients keep full access
+ALTER TABLE clients
+ADD scopes TEXT[] NOT NULL DEFAULT '{accounts:read,accounts:write,clients:write}';
+ALTER TABLE clients
+ALTER COLUMN scopes DROP DEFAULT;
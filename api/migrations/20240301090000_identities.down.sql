@@ -0,0 +1,7 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS identities;
+-- without their identities these users can't log in anymore
+DELETE FROM users
+WHERE password_hash IS NULL;
+ALTER TABLE users
+ALTER COLUMN password_hash SET NOT NULL;
@@ -0,0 +1,16 @@
+-- Add up migration script here
+-- users who only log in through an identity provider have no password
+ALTER TABLE users
+ALTER COLUMN password_hash DROP NOT NULL;
+CREATE TABLE IF NOT EXISTS identities (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    provider TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    email TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_login_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (provider, subject)
+);
+CREATE INDEX idx_identities_user_id
+ON identities (user_id);
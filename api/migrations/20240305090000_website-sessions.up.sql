@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- Sessions of the website, in the layout axum_session's SessionPgPool expects.
+CREATE TABLE IF NOT EXISTS sessions (
+    id VARCHAR(128) NOT NULL PRIMARY KEY,
+    expires BIGINT,
+    session TEXT NOT NULL
+);
+CREATE INDEX idx_sessions_expires ON sessions (expires);
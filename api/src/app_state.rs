@@ -1,4 +1,14 @@
-use crate::{db_init, repositories};
+use crate::{
+    db_init,
+    login_throttle::LoginThrottle,
+    notifier::{self, Notifier},
+    password::PasswordResetConfig,
+    repositories,
+};
+use shared::{
+    openfga::{Authorizer, OpenFgaClient},
+    openfga_model::AuthorizationModel,
+};
 use sqlx::{Pool, Postgres};
 use std::sync::Arc;
 
@@ -6,6 +16,10 @@ use std::sync::Arc;
 pub struct AppState {
     pub db: Pool<Postgres>,
     pub repo: repositories::RepoImpls,
+    pub openfga: Arc<dyn Authorizer>,
+    pub login_throttle: LoginThrottle,
+    pub notifier: Arc<dyn Notifier>,
+    pub password_reset: PasswordResetConfig,
 }
 
 pub async fn create_app_state() -> Arc<AppState> {
@@ -13,6 +27,31 @@ pub async fn create_app_state() -> Arc<AppState> {
     let app_state = Arc::new(AppState {
         db: pool.clone(),
         repo: repositories::create_repositories().await,
+        openfga: Arc::new(openfga_connect().await),
+        login_throttle: LoginThrottle::default(),
+        notifier: notifier::from_env(),
+        password_reset: PasswordResetConfig::from_env(),
     });
     app_state
 }
+
+/// Provisions the store named `FGA_STORE_NAME` with the model in `model.fga`.
+async fn openfga_connect() -> OpenFgaClient {
+    let store_name =
+        std::env::var("FGA_STORE_NAME").unwrap_or_else(|_| "rust-template".to_string());
+    let model = AuthorizationModel::from_dsl(include_str!("../model.fga"))
+        .expect("model.fga must be a valid OpenFGA model");
+    match OpenFgaClient::from_env()
+        .provision(&store_name, &model)
+        .await
+    {
+        Ok(client) => {
+            tracing::info!("✅OpenFGA store {} is ready", store_name);
+            client
+        }
+        Err(err) => {
+            tracing::error!("🔥 Failed to provision the OpenFGA store: {:?}", err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::app_state::AppState;
+use crate::client_repository::ClientRepo;
+use crate::repositories::Repositories;
+use axum::{
+    extract::{State, TypedHeader},
+    headers::authorization::{Authorization, Bearer},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use shared::{authz::Principal, error::Error};
+use std::sync::Arc;
+
+/// Like `shared::auth::token_auth`, but validates the client token against
+/// our own database instead of calling back into the api-server over HTTP.
+/// Inserts both the `ClientModel` and the owning user's `Principal`.
+pub async fn client_token_auth<B>(
+    State(data): State<Arc<AppState>>,
+    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, Error> {
+    let client = data
+        .repo
+        .client()
+        .get_client(auth.token().to_string())
+        .await
+        .map_err(|e| {
+            tracing::info!("Client token rejected: {}", e);
+            Error::Unauthorized
+        })?;
+    request
+        .extensions_mut()
+        .insert(Principal::user(client.user_id));
+    request.extensions_mut().insert(client);
+    Ok(next.run(request).await)
+}
@@ -1,69 +1,228 @@
-use crate::db_init::Db;
-use anyhow::Context;
+use crate::{
+    client_token::{ClientToken, TokenHasher},
+    db_init::Db,
+    openfga_outbox,
+};
 use axum::async_trait;
+use chrono::{DateTime, Utc};
 use mockall::automock;
-use rand::rngs::OsRng;
-use rand::{prelude::SliceRandom, CryptoRng, Rng};
-use shared::{error::Error, model::ClientModel, tracing::make_otel_db_span};
+use shared::{
+    error::Error,
+    model::{ClientModel, CreatedClientModel},
+    openfga::make_tuple,
+    tracing::make_otel_db_span,
+};
 use sqlx::Execute;
+use std::time::Duration;
 use tracing::{self, Instrument};
 use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct ClientRepoImpl {
     pool: Db,
+    hasher: TokenHasher,
 }
 impl ClientRepoImpl {
-    pub fn new(pool: Db) -> Self {
-        Self { pool }
+    pub fn new(pool: Db, hasher: TokenHasher) -> Self {
+        Self { pool, hasher }
     }
 }
 
 #[automock]
 #[async_trait]
 pub trait ClientRepo {
-    async fn create_client(&self, user_id: Uuid, name: String) -> Result<ClientModel, Error>;
+    async fn create_client(
+        &self,
+        user_id: Uuid,
+        name: String,
+        scopes: Vec<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<CreatedClientModel, Error>;
+    /// Validates a token, rejecting revoked and expired clients, and records
+    /// when the client was last used.
     async fn get_client(&self, token: String) -> Result<ClientModel, Error>;
+    /// Revokes the client's token for good. Revoking twice is a no-op.
+    async fn revoke_client(&self, client_id: Uuid) -> Result<ClientModel, Error>;
+    /// Issues a new token. The previous one keeps working for `grace_period`.
+    async fn rotate_client_token(
+        &self,
+        client_id: Uuid,
+        grace_period: Duration,
+    ) -> Result<CreatedClientModel, Error>;
 }
 
+#[derive(Debug)]
+struct StoredClient {
+    id: Uuid,
+    name: String,
+    user_id: Uuid,
+    scopes: Vec<String>,
+    expires_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    token_hash: String,
+    previous_token_hash: Option<String>,
+}
+
+// Writing on every request would turn each API call into a row update.
+const LAST_USED_RESOLUTION_SECONDS: f64 = 60.0;
+
 #[async_trait]
 impl ClientRepo for ClientRepoImpl {
-    async fn create_client(&self, user_id: Uuid, name: String) -> Result<ClientModel, Error> {
-        let mut rng = OsRng;
-        let random_string = generate_random_string(&mut rng, 32);
-        println!("{}", random_string);
+    async fn create_client(
+        &self,
+        user_id: Uuid,
+        name: String,
+        scopes: Vec<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<CreatedClientModel, Error> {
+        let token = ClientToken::generate(Uuid::new_v4());
         let query = sqlx::query_as!(
             ClientModel,
-            "INSERT INTO clients (user_id, token, name) VALUES ($1, $2, $3) RETURNING *",
+            r#"
+        INSERT INTO clients (id, user_id, token_hash, name, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, name, user_id, scopes, expires_at, revoked_at, last_used_at
+        "#,
+            token.client_id,
             user_id,
-            random_string,
+            self.hasher.hash(&token.secret),
             name,
+            &scopes,
+            expires_at,
         );
         let sql = query.sql().clone();
+        let mut tx = self.pool.begin().await?;
         let client = query
-            .fetch_one(&*self.pool)
+            .fetch_one(&mut *tx)
             .instrument(make_otel_db_span("INSERT", sql))
             .await?;
-        Ok(client)
+        openfga_outbox::enqueue_write(
+            &mut tx,
+            make_tuple(
+                &format!("user:{}", user_id),
+                "owner",
+                &format!("client:{}", client.id),
+            ),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(CreatedClientModel {
+            client,
+            token: token.expose(),
+        })
     }
 
     async fn get_client(&self, token: String) -> Result<ClientModel, Error> {
-        let query = sqlx::query_as!(ClientModel, "SELECT * FROM clients WHERE token = $1", token);
+        let token = ClientToken::parse(&token).ok_or(Error::Unauthorized)?;
+        let query = sqlx::query_as!(
+            StoredClient,
+            r#"
+        SELECT id, name, user_id, scopes, expires_at, revoked_at, last_used_at, token_hash,
+            CASE WHEN previous_token_expires_at > now() THEN previous_token_hash END AS "previous_token_hash?"
+        FROM clients
+        WHERE id = $1
+        "#,
+            token.client_id
+        );
         let sql = query.sql().clone();
         let client = query
-            .fetch_one(&*self.pool)
+            .fetch_optional(&*self.pool)
             .instrument(make_otel_db_span("SELECT", sql))
-            .await
-            .context("Failed to get client.")?;
+            .await?
+            .ok_or(Error::Unauthorized)?;
+
+        let current = self.hasher.verify(&token.secret, &client.token_hash);
+        let previous = client
+            .previous_token_hash
+            .as_deref()
+            .is_some_and(|hash| self.hasher.verify(&token.secret, hash));
+        if !current && !previous {
+            return Err(Error::Unauthorized);
+        }
+
+        let mut client = ClientModel {
+            id: client.id,
+            name: client.name,
+            user_id: client.user_id,
+            scopes: client.scopes,
+            expires_at: client.expires_at,
+            revoked_at: client.revoked_at,
+            last_used_at: client.last_used_at,
+        };
+        if !client.is_active(Utc::now()) {
+            tracing::info!("Client {} is revoked or expired", client.id);
+            return Err(Error::Unauthorized);
+        }
+
+        let query = sqlx::query_scalar!(
+            r#"
+        UPDATE clients
+        SET last_used_at = now()
+        WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < now() - make_interval(secs => $2))
+        RETURNING last_used_at
+        "#,
+            client.id,
+            LAST_USED_RESOLUTION_SECONDS,
+        );
+        let sql = query.sql().clone();
+        if let Some(last_used_at) = query
+            .fetch_optional(&*self.pool)
+            .instrument(make_otel_db_span("UPDATE", sql))
+            .await?
+        {
+            client.last_used_at = last_used_at;
+        }
         Ok(client)
     }
-}
 
-fn generate_random_string<R: Rng + CryptoRng>(rng: &mut R, length: usize) -> String {
-    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
-                            abcdefghijklmnopqrstuvwxyz\
-                            0123456789";
-    let random_bytes: Vec<u8> = (0..length).map(|_| *CHARSET.choose(rng).unwrap()).collect();
+    async fn revoke_client(&self, client_id: Uuid) -> Result<ClientModel, Error> {
+        let query = sqlx::query_as!(
+            ClientModel,
+            r#"
+        UPDATE clients
+        SET revoked_at = COALESCE(revoked_at, now())
+        WHERE id = $1
+        RETURNING id, name, user_id, scopes, expires_at, revoked_at, last_used_at
+        "#,
+            client_id
+        );
+        let sql = query.sql().clone();
+        let client = query
+            .fetch_one(&*self.pool)
+            .instrument(make_otel_db_span("UPDATE", sql))
+            .await?;
+        Ok(client)
+    }
 
-    String::from_utf8(random_bytes).unwrap()
+    async fn rotate_client_token(
+        &self,
+        client_id: Uuid,
+        grace_period: Duration,
+    ) -> Result<CreatedClientModel, Error> {
+        let token = ClientToken::generate(client_id);
+        let query = sqlx::query_as!(
+            ClientModel,
+            r#"
+        UPDATE clients
+        SET previous_token_hash = token_hash,
+            previous_token_expires_at = now() + make_interval(secs => $3),
+            token_hash = $2
+        WHERE id = $1 AND revoked_at IS NULL
+        RETURNING id, name, user_id, scopes, expires_at, revoked_at, last_used_at
+        "#,
+            client_id,
+            self.hasher.hash(&token.secret),
+            grace_period.as_secs_f64(),
+        );
+        let sql = query.sql().clone();
+        let client = query
+            .fetch_one(&*self.pool)
+            .instrument(make_otel_db_span("UPDATE", sql))
+            .await?;
+        Ok(CreatedClientModel {
+            client,
+            token: token.expose(),
+        })
+    }
 }
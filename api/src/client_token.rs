@@ -0,0 +1,133 @@
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::{prelude::SliceRandom, CryptoRng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Identifies our tokens in logs and secret scanners.
+pub const TOKEN_PREFIX: &str = "rtc";
+const SECRET_LENGTH: usize = 32;
+
+/// A client token of the form `rtc_<client id>_<secret>`.
+///
+/// The id lets us find the client without storing anything searchable about
+/// the secret; only an HMAC of the secret is persisted.
+#[derive(Debug)]
+pub struct ClientToken {
+    pub client_id: Uuid,
+    pub secret: Secret<String>,
+}
+
+impl ClientToken {
+    pub fn generate(client_id: Uuid) -> Self {
+        Self {
+            client_id,
+            secret: Secret::new(generate_random_string(&mut OsRng, SECRET_LENGTH)),
+        }
+    }
+
+    pub fn parse(token: &str) -> Option<Self> {
+        let mut parts = token.splitn(3, '_');
+        if parts.next()? != TOKEN_PREFIX {
+            return None;
+        }
+        let client_id = Uuid::try_parse(parts.next()?).ok()?;
+        let secret = parts.next().filter(|secret| !secret.is_empty())?;
+        Some(Self {
+            client_id,
+            secret: Secret::new(secret.to_string()),
+        })
+    }
+
+    /// The full token, to be shown to the user exactly once.
+    pub fn expose(&self) -> String {
+        format!(
+            "{}_{}_{}",
+            TOKEN_PREFIX,
+            self.client_id.simple(),
+            self.secret.expose_secret()
+        )
+    }
+}
+
+/// HMAC-SHA256 of token secrets, keyed with `CLIENT_TOKEN_HMAC_KEY`.
+#[derive(Debug, Clone)]
+pub struct TokenHasher {
+    key: Secret<String>,
+}
+
+impl TokenHasher {
+    pub fn new(key: Secret<String>) -> Self {
+        Self { key }
+    }
+
+    pub fn from_env() -> Self {
+        let key =
+            std::env::var("CLIENT_TOKEN_HMAC_KEY").expect("CLIENT_TOKEN_HMAC_KEY must be set");
+        Self::new(Secret::new(key))
+    }
+
+    pub fn hash(&self, secret: &Secret<String>) -> String {
+        let mut mac = self.mac();
+        mac.update(secret.expose_secret().as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Constant-time comparison of `secret` against a stored hash.
+    pub fn verify(&self, secret: &Secret<String>, hash: &str) -> bool {
+        let Ok(expected) = hex::decode(hash) else {
+            return false;
+        };
+        let mut mac = self.mac();
+        mac.update(secret.expose_secret().as_bytes());
+        mac.verify_slice(&expected).is_ok()
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(self.key.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any length")
+    }
+}
+
+pub fn generate_random_string<R: Rng + CryptoRng>(rng: &mut R, length: usize) -> String {
+    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+                            abcdefghijklmnopqrstuvwxyz\
+                            0123456789";
+    let random_bytes: Vec<u8> = (0..length).map(|_| *CHARSET.choose(rng).unwrap()).collect();
+
+    String::from_utf8(random_bytes).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client_token::*;
+
+    #[test]
+    fn test_token_round_trip() {
+        let client_id = Uuid::new_v4();
+        let token = ClientToken::generate(client_id).expose();
+        assert!(token.starts_with("rtc_"));
+
+        let parsed = ClientToken::parse(&token).unwrap();
+        assert_eq!(parsed.client_id, client_id);
+        assert_eq!(parsed.expose(), token);
+
+        assert!(ClientToken::parse("rtc_not-a-uuid_secret").is_none());
+        assert!(ClientToken::parse(&format!("abc_{}_secret", client_id.simple())).is_none());
+        assert!(ClientToken::parse(&format!("rtc_{}_", client_id.simple())).is_none());
+    }
+
+    #[test]
+    fn test_hash_and_verify() {
+        let hasher = TokenHasher::new(Secret::new("key".to_string()));
+        let token = ClientToken::generate(Uuid::new_v4());
+        let hash = hasher.hash(&token.secret);
+
+        assert!(hasher.verify(&token.secret, &hash));
+        assert!(!hasher.verify(&Secret::new("guess".to_string()), &hash));
+        assert!(!hasher.verify(&token.secret, ""));
+        let other_key = TokenHasher::new(Secret::new("other".to_string()));
+        assert!(!other_key.verify(&token.secret, &hash));
+    }
+}
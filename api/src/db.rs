@@ -1,9 +1,11 @@
-use crate::app_state::AppState;
+use crate::{app_state::AppState, openfga_outbox};
 use anyhow::Result;
 use axum::extract::{Query, State};
 use shared::{
+    authz::Principal,
     error::Error,
     model::{AccountModel, ClientModel},
+    openfga::make_tuple,
     schema,
     tracing::make_otel_db_span,
 };
@@ -23,7 +25,7 @@ pub async fn get_client_list(
 
     let query = sqlx::query_as!(
         ClientModel,
-        "SELECT * FROM clients ORDER by id LIMIT $1 OFFSET $2",
+        "SELECT id, name, user_id, scopes, expires_at, revoked_at, last_used_at FROM clients ORDER by id LIMIT $1 OFFSET $2",
         limit as i32,
         offset as i32
     );
@@ -79,9 +81,11 @@ pub async fn get_account_by_name(
 
 pub async fn create_account(
     account: schema::CreateAccount,
+    owner: Principal,
     State(data): State<Arc<AppState>>,
 ) -> Result<AccountModel, Error> {
     tracing::debug!("creating account");
+    let mut tx = data.db.begin().await?;
     let query = sqlx::query_as!(
         AccountModel,
         "INSERT INTO accounts(name, credential) VALUES ($1, $2) RETURNING *",
@@ -90,9 +94,15 @@ pub async fn create_account(
     );
     let sql = query.sql().clone();
     let query_result = query
-        .fetch_one(&data.db)
+        .fetch_one(&mut *tx)
         .instrument(make_otel_db_span("INSERT", sql))
         .await?;
+    openfga_outbox::enqueue_write(
+        &mut tx,
+        make_tuple(&owner.0, "owner", &format!("account:{}", query_result.id)),
+    )
+    .await?;
+    tx.commit().await?;
 
     Ok(query_result)
 }
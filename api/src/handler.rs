@@ -1,18 +1,29 @@
 use crate::client_repository::ClientRepo;
 use crate::repositories::Repositories;
 use crate::user_repository::UserRepo;
-use crate::{app_state::AppState, db};
+use crate::{app_state::AppState, db, login_throttle::Account, usecases};
 use axum::{
     debug_handler,
     extract::{Path, Query, State},
+    http::StatusCode,
     response::{Html, IntoResponse},
-    Json,
+    Extension, Json,
 };
+use chrono::Utc;
+use secrecy::Secret;
 use serde::Serialize;
-use shared::schema::{CreateClient, LoginPayload, PathName, ValidateToken};
+use shared::authz::Principal;
+use shared::client_ip::ClientIp;
+use shared::credential::Credential;
+use shared::model::ClientModel;
+use shared::schema::{
+    ChangePassword, CreateClient, LinkIdentity, LoginPayload, PathName, PathUserRole,
+    RequestPasswordReset, ResetPassword, RotateClient, ValidateToken,
+};
 use shared::{
     error::Error,
     schema::{CreateAccount, FilterOptions, PathId},
+    scope,
 };
 use std::sync::Arc;
 
@@ -57,9 +68,10 @@ pub async fn search_account(
 #[tracing::instrument]
 pub async fn create_account(
     State(data): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
     Json(payload): Json<CreateAccount>,
 ) -> Result<impl IntoResponse, Error> {
-    let account = db::create_account(payload, State(data)).await?;
+    let account = db::create_account(payload, principal, State(data)).await?;
     let json_response = serde_json::json!({
         "data": account
     });
@@ -92,36 +104,210 @@ pub async fn create_user(
 #[tracing::instrument]
 pub async fn validate_user(
     State(data): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
     Json(payload): Json<LoginPayload>,
 ) -> Result<impl IntoResponse, Error> {
-    let user = data.repo.user().validate_credentials(payload).await?;
-    Ok(wrap_response(user))
+    let account = Account::Name(payload.name.clone());
+    data.login_throttle.check(&account, ip)?;
+    match data.repo.user().validate_credentials(payload).await {
+        Ok(user) => {
+            data.login_throttle.record_success(&account, ip);
+            Ok(wrap_response(user))
+        }
+        Err(Error::Unauthorized) => {
+            data.login_throttle.record_failure(&account, ip);
+            Err(Error::Unauthorized)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Wrong current passwords count towards the login lockout of the user's
+/// id.
+#[tracing::instrument]
+pub async fn change_password(
+    Path(id): Path<PathId>,
+    State(data): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<ChangePassword>,
+) -> Result<impl IntoResponse, Error> {
+    let account = Account::Id(id.id);
+    data.login_throttle.check(&account, ip)?;
+    let res = usecases::change_password(
+        &data.repo,
+        id.id,
+        Secret::new(payload.current_password),
+        Secret::new(payload.new_password),
+    )
+    .await;
+    match res {
+        Ok(()) => {
+            data.login_throttle.record_success(&account, ip);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(Error::Unauthorized) => {
+            data.login_throttle.record_failure(&account, ip);
+            Err(Error::Unauthorized)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Accepted whether or not the user exists or could be notified, unless the
+/// client IP asked for too many resets.
+#[tracing::instrument]
+pub async fn request_password_reset(
+    State(data): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<RequestPasswordReset>,
+) -> Result<impl IntoResponse, Error> {
+    data.login_throttle.check_reset(ip)?;
+    data.login_throttle.record_reset(ip);
+    let res = usecases::request_password_reset(
+        &data.repo,
+        data.notifier.as_ref(),
+        &data.password_reset,
+        &payload.name,
+    )
+    .await;
+    if let Err(e) = res {
+        tracing::error!("Failed to send a password reset: {:?}", e);
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Unusable tokens count towards the client IP's reset limit, so tokens
+/// can't be guessed.
+#[tracing::instrument]
+pub async fn reset_password(
+    State(data): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<ResetPassword>,
+) -> Result<impl IntoResponse, Error> {
+    data.login_throttle.check_reset(ip)?;
+    let res = usecases::reset_password(
+        &data.repo,
+        Secret::new(payload.token),
+        Secret::new(payload.new_password),
+    )
+    .await;
+    if let Err(Error::Unauthorized) = res {
+        data.login_throttle.record_reset(ip);
+    }
+    Ok(wrap_response(res?))
 }
 
 pub async fn get_user(
     Path(id): Path<PathId>,
     State(data): State<Arc<AppState>>,
+    Extension(credential): Extension<Credential>,
 ) -> Result<impl IntoResponse, Error> {
+    usecases::check_user_access(&credential, id.id)?;
     let user = data.repo.user().get_user(id.id).await?;
     Ok(wrap_response(user))
 }
 
+pub async fn get_user_permissions(
+    Path(id): Path<PathId>,
+    State(data): State<Arc<AppState>>,
+    Extension(credential): Extension<Credential>,
+) -> Result<impl IntoResponse, Error> {
+    usecases::check_user_access(&credential, id.id)?;
+    let permissions = usecases::permissions(&data.repo, id.id).await?;
+    Ok(wrap_response(permissions))
+}
+
+#[tracing::instrument]
+pub async fn grant_role(
+    Path(path): Path<PathUserRole>,
+    State(data): State<Arc<AppState>>,
+    Extension(client): Extension<ClientModel>,
+) -> Result<impl IntoResponse, Error> {
+    let permissions =
+        usecases::set_role(&data.repo, client.user_id, path.id, &path.role, true).await?;
+    Ok(wrap_response(permissions))
+}
+
+#[tracing::instrument]
+pub async fn revoke_role(
+    Path(path): Path<PathUserRole>,
+    State(data): State<Arc<AppState>>,
+    Extension(client): Extension<ClientModel>,
+) -> Result<impl IntoResponse, Error> {
+    let permissions =
+        usecases::set_role(&data.repo, client.user_id, path.id, &path.role, false).await?;
+    Ok(wrap_response(permissions))
+}
+
+/// Linking to an existing user (`user_id`) takes over logins for it, so only
+/// services we trust to have authenticated that user may ask for it.
+#[tracing::instrument]
+pub async fn link_identity(
+    State(data): State<Arc<AppState>>,
+    Extension(credential): Extension<Credential>,
+    Json(payload): Json<LinkIdentity>,
+) -> Result<impl IntoResponse, Error> {
+    if payload.user_id.is_some() && !matches!(credential, Credential::ApiKey { .. }) {
+        tracing::info!("Only services may link identities to existing users");
+        return Err(Error::Forbidden);
+    }
+    let user = data
+        .repo
+        .user()
+        .get_or_create_identity_user(payload)
+        .await?;
+    Ok(wrap_response(user))
+}
+
 // Client routes
 
 #[tracing::instrument]
 pub async fn create_client(
     State(data): State<Arc<AppState>>,
+    Extension(credential): Extension<Credential>,
     Json(payload): Json<CreateClient>,
 ) -> Result<impl IntoResponse, Error> {
+    let user_id = usecases::client_owner(&credential, payload.user_id)?;
+    let scopes = payload
+        .scopes
+        .unwrap_or_else(|| scope::DEFAULT.iter().map(|s| s.to_string()).collect());
+    usecases::check_client_scopes(&data.repo, user_id, &scopes).await?;
+    let expires_at = usecases::client_expires_at(Utc::now(), payload.expires_in_seconds)?;
     let client = data
         .repo
         .client()
-        .create_client(payload.user_id, payload.name)
+        .create_client(user_id, payload.name, scopes, expires_at)
         .await?;
     Ok(wrap_response(client))
 }
 
 #[tracing::instrument]
+pub async fn revoke_client(
+    Path(id): Path<PathId>,
+    State(data): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, Error> {
+    let client = data.repo.client().revoke_client(id.id).await?;
+    Ok(wrap_response(client))
+}
+
+#[tracing::instrument]
+pub async fn rotate_client_token(
+    Path(id): Path<PathId>,
+    State(data): State<Arc<AppState>>,
+    payload: Option<Json<RotateClient>>,
+) -> Result<impl IntoResponse, Error> {
+    let grace_period = usecases::rotation_grace_period(
+        payload.and_then(|Json(payload)| payload.grace_period_seconds),
+    )?;
+    let client = data
+        .repo
+        .client()
+        .rotate_client_token(id.id, grace_period)
+        .await?;
+    Ok(wrap_response(client))
+}
+
+#[tracing::instrument(skip(payload))]
 pub async fn get_client_by_token(
     State(data): State<Arc<AppState>>,
     Json(payload): Json<ValidateToken>,
@@ -0,0 +1,363 @@
+//! Slows down password guessing on `POST /api/users/login` and password
+//! changes. Failed attempts are counted per account and per client IP; past
+//! a number of free attempts each further failure locks the key out for
+//! twice as long as the previous one. Password resets are limited per client
+//! IP on their own. Counters live in memory, so each replica counts on its
+//! own.
+use moka::sync::Cache;
+use shared::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy)]
+struct Policy {
+    free_attempts: u32,
+    first_lockout: Duration,
+    max_lockout: Duration,
+}
+
+impl Policy {
+    fn lockout(&self, failures: u32) -> Option<Duration> {
+        let over = failures.checked_sub(self.free_attempts + 1)?;
+        let lockout = self
+            .first_lockout
+            .checked_mul(2u32.saturating_pow(over))
+            .unwrap_or(self.max_lockout);
+        Some(lockout.min(self.max_lockout))
+    }
+}
+
+const ACCOUNT_POLICY: Policy = Policy {
+    free_attempts: 5,
+    first_lockout: Duration::from_secs(30),
+    max_lockout: Duration::from_secs(15 * 60),
+};
+// Shared by everyone behind the same NAT, so more lenient.
+const IP_POLICY: Policy = Policy {
+    free_attempts: 20,
+    first_lockout: Duration::from_secs(30),
+    max_lockout: Duration::from_secs(60 * 60),
+};
+// Every reset request sends a notification, so few are free.
+const RESET_POLICY: Policy = Policy {
+    free_attempts: 5,
+    first_lockout: Duration::from_secs(60),
+    max_lockout: Duration::from_secs(60 * 60),
+};
+/// Counters are forgotten after this long without a failure.
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+const MAX_ENTRIES: u64 = 100_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Attempts {
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Counter<K: Hash + Eq + Send + Sync + 'static> {
+    policy: Policy,
+    attempts: Cache<K, Attempts>,
+}
+
+impl<K: Hash + Eq + Send + Sync + 'static> Counter<K> {
+    fn new(policy: Policy) -> Self {
+        Self {
+            policy,
+            attempts: Cache::builder()
+                .time_to_idle(FORGET_AFTER)
+                .max_capacity(MAX_ENTRIES)
+                .build(),
+        }
+    }
+
+    fn locked_for(&self, key: &K, now: Instant) -> Option<Duration> {
+        let locked_until = self.attempts.get(key)?.locked_until?;
+        locked_until
+            .checked_duration_since(now)
+            .filter(|d| !d.is_zero())
+    }
+
+    fn record_failure(&self, key: K, now: Instant) -> Attempts {
+        let policy = self.policy;
+        self.attempts
+            .entry(key)
+            .and_upsert_with(|entry| {
+                let failures = entry.map_or(0, |e| e.into_value().failures) + 1;
+                Attempts {
+                    failures,
+                    locked_until: policy.lockout(failures).map(|lockout| now + lockout),
+                }
+            })
+            .into_value()
+    }
+
+    fn reset(&self, key: &K) {
+        self.attempts.invalidate(key);
+    }
+}
+
+/// Whose password is being guessed: a login name, or a user id where the
+/// caller names the user by id. Kept apart, so a user named like another
+/// user's id can't lock that user out.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Account {
+    Name(String),
+    Id(Uuid),
+}
+
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Account::Name(name) => write!(f, "{}", name),
+            Account::Id(id) => write!(f, "id:{}", id),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LoginThrottle {
+    accounts: Counter<Account>,
+    ips: Counter<IpAddr>,
+    resets: Counter<IpAddr>,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        Self {
+            accounts: Counter::new(ACCOUNT_POLICY),
+            ips: Counter::new(IP_POLICY),
+            resets: Counter::new(RESET_POLICY),
+        }
+    }
+}
+
+fn too_many_requests(locked_for: Duration) -> Error {
+    Error::TooManyRequests {
+        // round up so retrying on time succeeds
+        retry_after_seconds: locked_for.as_secs() + 1,
+    }
+}
+
+impl LoginThrottle {
+    /// `Error::TooManyRequests` while the account or the IP is locked out.
+    /// Checked before the password, so locked out attempts cost no hashing
+    /// and don't count as failures.
+    pub fn check(&self, account: &Account, ip: IpAddr) -> Result<(), Error> {
+        self.check_at(account, ip, Instant::now())
+    }
+
+    fn check_at(&self, account: &Account, ip: IpAddr, now: Instant) -> Result<(), Error> {
+        let locked_for = self
+            .accounts
+            .locked_for(account, now)
+            .max(self.ips.locked_for(&ip, now));
+        match locked_for {
+            Some(locked_for) => {
+                tracing::warn!(
+                    target: "audit",
+                    event = "login_throttled",
+                    user = %account,
+                    %ip,
+                    "Login attempt while locked out"
+                );
+                Err(too_many_requests(locked_for))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Counts a failed login, for unknown account names too so lockouts
+    /// don't reveal which names exist.
+    pub fn record_failure(&self, account: &Account, ip: IpAddr) {
+        self.record_failure_at(account, ip, Instant::now())
+    }
+
+    fn record_failure_at(&self, account: &Account, ip: IpAddr, now: Instant) {
+        let by_account = self.accounts.record_failure(account.clone(), now);
+        let by_ip = self.ips.record_failure(ip, now);
+        tracing::warn!(
+            target: "audit",
+            event = "login_failed",
+            user = %account,
+            %ip,
+            account_failures = by_account.failures,
+            ip_failures = by_ip.failures,
+            "Failed login"
+        );
+        if by_account.locked_until.is_some() || by_ip.locked_until.is_some() {
+            tracing::warn!(
+                target: "audit",
+                event = "login_locked_out",
+                user = %account,
+                %ip,
+                account_locked = by_account.locked_until.is_some(),
+                ip_locked = by_ip.locked_until.is_some(),
+                "Login locked out"
+            );
+        }
+    }
+
+    /// Forgets the account's failures. The IP's are kept, or one valid
+    /// account would let an attacker guess at all others.
+    pub fn record_success(&self, account: &Account, ip: IpAddr) {
+        self.accounts.reset(account);
+        tracing::info!(target: "audit", event = "login_succeeded", user = %account, %ip, "Login");
+    }
+
+    /// `Error::TooManyRequests` while the IP is locked out of password
+    /// resets. Checked before looking at the request, so it costs nothing.
+    pub fn check_reset(&self, ip: IpAddr) -> Result<(), Error> {
+        self.check_reset_at(ip, Instant::now())
+    }
+
+    fn check_reset_at(&self, ip: IpAddr, now: Instant) -> Result<(), Error> {
+        match self.resets.locked_for(&ip, now) {
+            Some(locked_for) => {
+                tracing::warn!(
+                    target: "audit",
+                    event = "password_reset_throttled",
+                    %ip,
+                    "Password reset while locked out"
+                );
+                Err(too_many_requests(locked_for))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Counts a reset request, or a reset with an unusable token.
+    pub fn record_reset(&self, ip: IpAddr) {
+        self.record_reset_at(ip, Instant::now())
+    }
+
+    fn record_reset_at(&self, ip: IpAddr, now: Instant) {
+        self.resets.record_failure(ip, now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::login_throttle::*;
+
+    #[test]
+    fn test_lockout_doubles_up_to_max() {
+        let lockouts: Vec<_> = (5..=12).map(|n| ACCOUNT_POLICY.lockout(n)).collect();
+        let secs = |s| Some(Duration::from_secs(s));
+        assert_eq!(
+            lockouts,
+            vec![
+                None,
+                secs(30),
+                secs(60),
+                secs(120),
+                secs(240),
+                secs(480),
+                secs(900),
+                secs(900)
+            ]
+        );
+        assert_eq!(ACCOUNT_POLICY.lockout(u32::MAX), secs(900));
+    }
+
+    #[test]
+    fn test_account_lockout() {
+        let throttle = LoginThrottle::default();
+        let alice = Account::Name("alice".to_string());
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let other_ip: IpAddr = "203.0.113.8".parse().unwrap();
+        let now = Instant::now();
+
+        for _ in 0..ACCOUNT_POLICY.free_attempts {
+            assert!(throttle.check_at(&alice, ip, now).is_ok());
+            throttle.record_failure_at(&alice, ip, now);
+        }
+        assert!(throttle.check_at(&alice, ip, now).is_ok());
+        throttle.record_failure_at(&alice, ip, now);
+
+        // locked out from everywhere, other accounts are fine
+        let res = throttle.check_at(&alice, other_ip, now);
+        assert!(matches!(
+            res,
+            Err(Error::TooManyRequests {
+                retry_after_seconds: 31
+            })
+        ));
+        assert!(throttle
+            .check_at(&Account::Name("bob".to_string()), ip, now)
+            .is_ok());
+
+        let later = now + Duration::from_secs(30);
+        assert!(throttle.check_at(&alice, ip, later).is_ok());
+        throttle.record_failure_at(&alice, ip, later);
+        let res = throttle.check_at(&alice, ip, later);
+        assert!(matches!(
+            res,
+            Err(Error::TooManyRequests {
+                retry_after_seconds: 61
+            })
+        ));
+
+        throttle.record_success(&alice, ip);
+        assert!(throttle.check_at(&alice, ip, later).is_ok());
+    }
+
+    #[test]
+    fn test_ip_lockout() {
+        let throttle = LoginThrottle::default();
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let now = Instant::now();
+
+        for n in 0..=IP_POLICY.free_attempts {
+            throttle.record_failure_at(&Account::Name(format!("user{}", n)), ip, now);
+        }
+        let someone = Account::Name("someone".to_string());
+        assert!(throttle.check_at(&someone, ip, now).is_err());
+        assert!(throttle
+            .check_at(&someone, "2001:db8::2".parse().unwrap(), now)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_ids_and_names_are_separate_accounts() {
+        let throttle = LoginThrottle::default();
+        let id = Uuid::new_v4();
+        let now = Instant::now();
+
+        let named_like_id = Account::Name(format!("id:{}", id));
+        for n in 0..=ACCOUNT_POLICY.free_attempts {
+            let ip = IpAddr::from([203, 0, 113, n as u8]);
+            throttle.record_failure_at(&named_like_id, ip, now);
+        }
+        let ip: IpAddr = "198.51.100.1".parse().unwrap();
+        assert!(throttle.check_at(&named_like_id, ip, now).is_err());
+        assert!(throttle.check_at(&Account::Id(id), ip, now).is_ok());
+    }
+
+    #[test]
+    fn test_reset_lockout() {
+        let throttle = LoginThrottle::default();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let now = Instant::now();
+
+        for _ in 0..=RESET_POLICY.free_attempts {
+            assert!(throttle.check_reset_at(ip, now).is_ok());
+            throttle.record_reset_at(ip, now);
+        }
+        assert!(matches!(
+            throttle.check_reset_at(ip, now),
+            Err(Error::TooManyRequests {
+                retry_after_seconds: 61
+            })
+        ));
+        // logins are counted apart
+        let alice = Account::Name("alice".to_string());
+        assert!(throttle.check_at(&alice, ip, now).is_ok());
+        assert!(throttle
+            .check_reset_at("203.0.113.8".parse().unwrap(), now)
+            .is_ok());
+    }
+}
@@ -1,8 +1,14 @@
 mod app_state;
+mod auth;
 mod client_repository;
+mod client_token;
 mod db;
 mod db_init;
 mod handler;
+mod login_throttle;
+mod notifier;
+mod openfga_outbox;
+mod password;
 mod repositories;
 mod router;
 #[cfg(test)]
@@ -23,7 +29,7 @@ async fn main() {
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     tracing::info!("listening on {}", addr);
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
@@ -0,0 +1,151 @@
+//! Delivers messages to users outside of the website, e.g. password reset
+//! links. Users have no email address yet, so the implementations here are
+//! for development: `NOTIFIER=log` (the default) logs messages, and
+//! `NOTIFIER=file` appends them to `NOTIFIER_FILE`.
+use anyhow::Context;
+use axum::async_trait;
+use mockall::automock;
+use secrecy::{ExposeSecret, Secret};
+use shared::{error::Error, model::UserTransportModel};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug)]
+pub enum Notification {
+    PasswordReset {
+        link: Secret<String>,
+        expires_in: Duration,
+    },
+}
+
+impl Notification {
+    pub fn subject(&self) -> &'static str {
+        match self {
+            Notification::PasswordReset { .. } => "Reset your password",
+        }
+    }
+
+    /// Contains secrets, don't log it outside of development.
+    pub fn body(&self) -> String {
+        match self {
+            Notification::PasswordReset { link, expires_in } => format!(
+                "Open {} within {} minutes to choose a new password.",
+                link.expose_secret(),
+                expires_in.as_secs() / 60
+            ),
+        }
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait Notifier: std::fmt::Debug + Send + Sync {
+    async fn notify(
+        &self,
+        user: &UserTransportModel,
+        notification: &Notification,
+    ) -> Result<(), Error>;
+}
+
+pub fn from_env() -> Arc<dyn Notifier> {
+    match std::env::var("NOTIFIER").as_deref() {
+        Ok("file") => {
+            let path = std::env::var("NOTIFIER_FILE").expect("Define NOTIFIER_FILE");
+            Arc::new(FileNotifier::new(path.into()))
+        }
+        Ok("log") | Err(_) => Arc::new(LogNotifier),
+        Ok(other) => panic!("Unknown NOTIFIER {}, use log or file", other),
+    }
+}
+
+#[derive(Debug)]
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn notify(
+        &self,
+        user: &UserTransportModel,
+        notification: &Notification,
+    ) -> Result<(), Error> {
+        tracing::warn!(
+            "Notification for user {} ({}): {}: {}",
+            user.name,
+            user.id,
+            notification.subject(),
+            notification.body()
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct FileNotifier {
+    path: PathBuf,
+}
+
+impl FileNotifier {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl Notifier for FileNotifier {
+    async fn notify(
+        &self,
+        user: &UserTransportModel,
+        notification: &Notification,
+    ) -> Result<(), Error> {
+        let line = format!(
+            "{}\t{}\t{}\t{}\n",
+            user.id,
+            user.name,
+            notification.subject(),
+            notification.body()
+        );
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .context("Failed to open the notifier file.")?;
+        file.write_all(line.as_bytes())
+            .await
+            .context("Failed to write a notification.")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::notifier::*;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_file_notifier_appends() {
+        let path = std::env::temp_dir().join(format!("notifications-{}", Uuid::new_v4()));
+        let notifier = FileNotifier::new(path.clone());
+        let user = UserTransportModel {
+            id: Uuid::new_v4(),
+            name: "alice".to_string(),
+        };
+        for token in ["first", "second"] {
+            let notification = Notification::PasswordReset {
+                link: Secret::new(format!("http://localhost/reset?token={}", token)),
+                expires_in: Duration::from_secs(3600),
+            };
+            notifier.notify(&user, &notification).await.unwrap();
+        }
+
+        let written = tokio::fs::read_to_string(&path).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+        let lines: Vec<_> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(&format!("{}\talice\tReset your password\t", user.id)));
+        assert!(lines[0].contains("token=first within 60 minutes"));
+        assert!(lines[1].contains("token=second"));
+    }
+}
@@ -0,0 +1,234 @@
+use shared::{
+    error::Error,
+    openfga::{Authorizer, RelationshipAction, RelationshipTuple, TupleKeys},
+    tracing::make_otel_db_span,
+};
+use sqlx::{Execute, PgConnection, Pool, Postgres};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{self, Instrument};
+
+const BATCH_SIZE: i64 = 20;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a claimed entry is hidden from other workers while we deliver it.
+const LEASE: Duration = Duration::from_secs(60);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+struct OutboxEntry {
+    id: i64,
+    action: String,
+    fga_user: String,
+    relation: String,
+    object: String,
+    attempts: i32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Delivered,
+    Retry,
+    Failed,
+}
+
+/// Queues `tuple` to be written to OpenFGA once `tx` commits.
+///
+/// Call this inside the transaction that inserts the row the tuple is about,
+/// so the relationship is delivered if and only if the row exists.
+pub async fn enqueue_write(tx: &mut PgConnection, tuple: RelationshipTuple) -> Result<(), Error> {
+    let query = sqlx::query!(
+        "INSERT INTO openfga_outbox (action, fga_user, relation, object) VALUES ('write', $1, $2, $3)",
+        tuple.user,
+        tuple.relation,
+        tuple.object,
+    );
+    let sql = query.sql().clone();
+    query
+        .execute(tx)
+        .instrument(make_otel_db_span("INSERT", sql))
+        .await?;
+    Ok(())
+}
+
+/// Delivers queued tuples to OpenFGA until the process exits.
+///
+/// Failed deliveries are retried with exponential backoff. Requests OpenFGA
+/// rejects as invalid (400) are not retried; they keep their error in
+/// `last_error` and are marked `failed_at` for an operator to look at.
+pub async fn run_worker(pool: Pool<Postgres>, openfga: Arc<dyn Authorizer>) {
+    tracing::info!("OpenFGA outbox worker started");
+    loop {
+        match deliver_batch(&pool, openfga.as_ref()).await {
+            // a full batch means more entries may be due right away
+            Ok(delivered) if delivered == BATCH_SIZE as usize => continue,
+            Ok(_) => {}
+            Err(e) => tracing::error!("OpenFGA outbox delivery failed: {:?}", e),
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn deliver_batch(pool: &Pool<Postgres>, openfga: &dyn Authorizer) -> Result<usize, Error> {
+    // Claim due entries by pushing them past the lease, so other replicas skip
+    // them and a crashed worker's entries become due again.
+    let entries = sqlx::query_as!(
+        OutboxEntry,
+        r#"
+        UPDATE openfga_outbox
+        SET available_at = now() + make_interval(secs => $2)
+        WHERE id IN (
+            SELECT id FROM openfga_outbox
+            WHERE delivered_at IS NULL AND failed_at IS NULL AND available_at <= now()
+            ORDER BY id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, action, fga_user, relation, object, attempts
+        "#,
+        BATCH_SIZE,
+        LEASE.as_secs_f64(),
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for entry in &entries {
+        deliver(pool, openfga, entry).await?;
+    }
+    Ok(entries.len())
+}
+
+#[tracing::instrument(skip(pool, openfga))]
+async fn deliver(
+    pool: &Pool<Postgres>,
+    openfga: &dyn Authorizer,
+    entry: &OutboxEntry,
+) -> Result<(), Error> {
+    let tuple_keys = TupleKeys {
+        tuple_keys: vec![RelationshipTuple {
+            user: entry.fga_user.clone(),
+            relation: entry.relation.clone(),
+            object: entry.object.clone(),
+        }],
+    };
+    let action = match entry.action.as_str() {
+        "delete" => RelationshipAction::Deletes(tuple_keys),
+        _ => RelationshipAction::Writes(tuple_keys),
+    };
+    let result = openfga.write_relationship_tuple(action).await;
+
+    match outcome(&entry.action, &result) {
+        Outcome::Delivered => {
+            sqlx::query!(
+                "UPDATE openfga_outbox SET delivered_at = now(), attempts = attempts + 1 WHERE id = $1",
+                entry.id
+            )
+            .execute(pool)
+            .await?;
+        }
+        Outcome::Failed => {
+            tracing::error!("Giving up on OpenFGA outbox entry: {:?}", result);
+            sqlx::query!(
+                "UPDATE openfga_outbox SET failed_at = now(), attempts = attempts + 1, last_error = $2 WHERE id = $1",
+                entry.id,
+                error_message(&result),
+            )
+            .execute(pool)
+            .await?;
+        }
+        Outcome::Retry => {
+            let delay = retry_delay(entry.attempts);
+            tracing::warn!("Retrying OpenFGA outbox entry in {:?}: {:?}", delay, result);
+            sqlx::query!(
+                r#"
+                UPDATE openfga_outbox
+                SET attempts = attempts + 1, last_error = $2, available_at = now() + make_interval(secs => $3)
+                WHERE id = $1
+                "#,
+                entry.id,
+                error_message(&result),
+                delay.as_secs_f64(),
+            )
+            .execute(pool)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+fn outcome<T>(action: &str, result: &Result<T, Error>) -> Outcome {
+    match result {
+        Ok(_) => Outcome::Delivered,
+        // A previous attempt may have reached OpenFGA without us recording it.
+        Err(Error::OpenFga {
+            status: 400,
+            message,
+            ..
+        }) if (action == "write" && message.contains("already exists"))
+            || (action == "delete" && message.contains("does not exist")) =>
+        {
+            Outcome::Delivered
+        }
+        Err(Error::OpenFga { status: 400, .. }) => Outcome::Failed,
+        Err(_) => Outcome::Retry,
+    }
+}
+
+fn retry_delay(attempts: i32) -> Duration {
+    let exponent = attempts.clamp(0, 16) as u32;
+    Duration::from_secs(2u64.pow(exponent)).min(MAX_RETRY_DELAY)
+}
+
+fn error_message<T>(result: &Result<T, Error>) -> Option<String> {
+    result.as_ref().err().map(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::openfga_outbox::*;
+
+    fn openfga_error(status: u16, message: &str) -> Result<(), Error> {
+        Err(Error::OpenFga {
+            status,
+            code: "write_failed_due_to_invalid_input".to_string(),
+            message: message.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_outcome() {
+        assert_eq!(outcome("write", &Ok(())), Outcome::Delivered);
+        assert_eq!(
+            outcome(
+                "write",
+                &openfga_error(400, "cannot write a tuple which already exists")
+            ),
+            Outcome::Delivered
+        );
+        assert_eq!(
+            outcome(
+                "delete",
+                &openfga_error(400, "cannot delete a tuple which does not exist")
+            ),
+            Outcome::Delivered
+        );
+        assert_eq!(
+            outcome("write", &openfga_error(400, "type 'team' not found")),
+            Outcome::Failed
+        );
+        assert_eq!(
+            outcome("write", &openfga_error(503, "unavailable")),
+            Outcome::Retry
+        );
+        assert_eq!(
+            outcome::<()>("write", &Err(Error::InternalServerError)),
+            Outcome::Retry
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(0), Duration::from_secs(1));
+        assert_eq!(retry_delay(3), Duration::from_secs(8));
+        assert_eq!(retry_delay(100), MAX_RETRY_DELAY);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::client_token::generate_random_string;
+use anyhow::Context;
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version,
+};
+use rand::rngs::OsRng;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use shared::error::Error;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const RESET_TOKEN_LENGTH: usize = 32;
+
+/// A random password reset token, to be sent to the user.
+pub fn generate_reset_token() -> Secret<String> {
+    Secret::new(generate_random_string(&mut OsRng, RESET_TOKEN_LENGTH))
+}
+
+/// What is stored of a reset token. Tokens are long and random, so unlike
+/// passwords a fast hash is enough.
+pub fn hash_reset_token(token: &Secret<String>) -> String {
+    hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
+}
+
+/// Where reset links point, `PASSWORD_RESET_URL` (default: the website's
+/// reset form), and how long they work, `PASSWORD_RESET_TTL_SECONDS`
+/// (default 3600).
+#[derive(Debug, Clone)]
+pub struct PasswordResetConfig {
+    pub url: String,
+    pub ttl: Duration,
+}
+
+impl PasswordResetConfig {
+    pub fn from_env() -> Self {
+        let url = std::env::var("PASSWORD_RESET_URL")
+            .unwrap_or_else(|_| "http://localhost:8000/password/reset".to_string());
+        let ttl = std::env::var("PASSWORD_RESET_TTL_SECONDS")
+            .map(|v| {
+                v.parse()
+                    .expect("PASSWORD_RESET_TTL_SECONDS must be a number")
+            })
+            .unwrap_or(3600);
+        Self {
+            url,
+            ttl: Duration::from_secs(ttl),
+        }
+    }
+
+    /// Tokens are alphanumeric, so need no escaping.
+    pub fn link(&self, token: &Secret<String>) -> Secret<String> {
+        Secret::new(format!("{}?token={}", self.url, token.expose_secret()))
+    }
+}
+
+/// Argon2id hashing of user passwords, with costs from `ARGON2_MEMORY_KIB`,
+/// `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM` (default: the argon2 crate's).
+///
+/// Hashing and verifying are slow on purpose, call them from
+/// `spawn_blocking`.
+#[derive(Debug)]
+pub struct PasswordHashing {
+    params: Params,
+    dummy_hash: OnceLock<String>,
+}
+
+impl PasswordHashing {
+    pub fn new(params: Params) -> Self {
+        Self {
+            params,
+            dummy_hash: OnceLock::new(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let cost = |name: &str, default: u32| match std::env::var(name) {
+            Ok(v) => v
+                .parse()
+                .unwrap_or_else(|_| panic!("{} must be a number", name)),
+            Err(_) => default,
+        };
+        let params = Params::new(
+            cost("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST),
+            cost("ARGON2_ITERATIONS", Params::DEFAULT_T_COST),
+            cost("ARGON2_PARALLELISM", Params::DEFAULT_P_COST),
+            None,
+        )
+        .expect("ARGON2_* must be valid argon2 parameters");
+        Self::new(params)
+    }
+
+    fn argon2(&self) -> Argon2<'_> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    pub fn hash(&self, password: &Secret<String>) -> String {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        self.argon2()
+            .hash_password(password.expose_secret().as_bytes(), &salt)
+            .expect("argon2 hashes passwords of any length")
+            .to_string()
+    }
+
+    /// A hash of a random password, to verify against when there is no
+    /// user so rejecting takes as long as for a wrong password.
+    pub fn dummy_hash(&self) -> String {
+        self.dummy_hash
+            .get_or_init(|| {
+                let password = SaltString::generate(&mut rand::thread_rng());
+                self.hash(&Secret::new(password.as_str().to_string()))
+            })
+            .clone()
+    }
+
+    /// `Error::Unauthorized` for a wrong password. On success, whether the
+    /// hash is weaker than we'd create now and should be replaced.
+    #[tracing::instrument(name = "Verify password hash", skip_all)]
+    pub fn verify(&self, expected_hash: &str, candidate: &Secret<String>) -> Result<bool, Error> {
+        let expected_hash = PasswordHash::new(expected_hash)
+            .context("Failed to parse hash in PHC string format.")
+            .map_err(|_| Error::InternalServerError)?;
+
+        // verifies with the parameters in the hash, whatever ours are
+        Argon2::default()
+            .verify_password(candidate.expose_secret().as_bytes(), &expected_hash)
+            .context("Invalid password.")
+            .map_err(|_| Error::Unauthorized)?;
+        Ok(self.needs_rehash(&expected_hash))
+    }
+
+    fn needs_rehash(&self, hash: &PasswordHash) -> bool {
+        if hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+        {
+            return true;
+        }
+        match Params::try_from(hash) {
+            Ok(params) => {
+                params.m_cost() < self.params.m_cost()
+                    || params.t_cost() < self.params.t_cost()
+                    || params.p_cost() < self.params.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::password::*;
+
+    fn hashing(m_cost: u32, t_cost: u32) -> PasswordHashing {
+        PasswordHashing::new(Params::new(m_cost, t_cost, 1, None).unwrap())
+    }
+
+    #[test]
+    fn test_hash_and_verify() {
+        let hashing = hashing(1024, 1);
+        let password = Secret::new("hunter2".to_string());
+        let hash = hashing.hash(&password);
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+
+        assert!(matches!(hashing.verify(&hash, &password), Ok(false)));
+        let wrong = Secret::new("hunter3".to_string());
+        assert!(matches!(
+            hashing.verify(&hash, &wrong),
+            Err(Error::Unauthorized)
+        ));
+        assert!(matches!(
+            hashing.verify(&hashing.dummy_hash(), &password),
+            Err(Error::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn test_reset_tokens() {
+        let token = generate_reset_token();
+        assert_eq!(token.expose_secret().len(), RESET_TOKEN_LENGTH);
+        assert_ne!(
+            token.expose_secret(),
+            generate_reset_token().expose_secret()
+        );
+        assert_eq!(hash_reset_token(&token), hash_reset_token(&token));
+        assert_ne!(&hash_reset_token(&token), token.expose_secret());
+    }
+
+    #[test]
+    fn test_rehash_weaker_hashes() {
+        let password = Secret::new("hunter2".to_string());
+        let weak = hashing(1024, 1).hash(&password);
+        let strong = hashing(2048, 2).hash(&password);
+
+        let hashing = hashing(2048, 1);
+        assert!(matches!(hashing.verify(&weak, &password), Ok(true)));
+        assert!(matches!(hashing.verify(&strong, &password), Ok(false)));
+
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let argon2i = Argon2::new(Algorithm::Argon2i, Version::V0x13, hashing.params.clone())
+            .hash_password(b"hunter2", &salt)
+            .unwrap()
+            .to_string();
+        assert!(matches!(hashing.verify(&argon2i, &password), Ok(true)));
+    }
+}
@@ -1,6 +1,8 @@
 use crate::{
     client_repository::{ClientRepo, ClientRepoImpl},
+    client_token::TokenHasher,
     db_init,
+    password::PasswordHashing,
     user_repository::{UserRepo, UserRepoImpl},
 };
 use std::sync::Arc;
@@ -8,8 +10,8 @@ use std::sync::Arc;
 pub async fn create_repositories() -> RepoImpls {
     let db_pool = Arc::new(db_init::db_connect().await);
     RepoImpls::new(
-        UserRepoImpl::new(db_pool.clone()),
-        ClientRepoImpl::new(db_pool),
+        UserRepoImpl::new(db_pool.clone(), PasswordHashing::from_env()),
+        ClientRepoImpl::new(db_pool, TokenHasher::from_env()),
     )
 }
 
@@ -1,9 +1,30 @@
+use crate::notifier::{Notification, Notifier};
+use crate::password::PasswordResetConfig;
 use crate::repositories::Repositories;
 use crate::user_repository::UserRepo;
-use shared::{error::Error, model::UserTransportModel};
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use shared::{
+    credential::Credential,
+    error::Error,
+    model::{UserPermissionsModel, UserTransportModel},
+    permission,
+    schema::MIN_PASSWORD_LENGTH,
+    scope,
+};
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
+/// Longest lifetime a client token can be created with, about ten years.
+const MAX_CLIENT_EXPIRY_SECONDS: u64 = 10 * 365 * 24 * 60 * 60;
+
+/// How long a rotated-out client token keeps working by default.
+const DEFAULT_ROTATION_GRACE_PERIOD_SECONDS: u64 = 24 * 60 * 60;
+
+/// Longest a rotated-out client token can be kept working, 30 days.
+const MAX_ROTATION_GRACE_PERIOD_SECONDS: u64 = 30 * 24 * 60 * 60;
+
 pub async fn view<R: Repositories>(
     repo: Arc<R>,
     user_id: Uuid,
@@ -11,6 +32,194 @@ pub async fn view<R: Repositories>(
     let user = repo.user().get_user(user_id).await?;
     Ok(user)
 }
+pub async fn permissions<R: Repositories>(
+    repo: &R,
+    user_id: Uuid,
+) -> Result<UserPermissionsModel, Error> {
+    let roles = repo.user().get_roles(user_id).await?;
+    Ok(UserPermissionsModel {
+        user_id,
+        permissions: permission::for_roles(&roles),
+        roles,
+    })
+}
+
+/// How long the previous token keeps working after a rotation,
+/// `Error::BadRequest` past `MAX_ROTATION_GRACE_PERIOD_SECONDS`.
+pub fn rotation_grace_period(grace_period_seconds: Option<u64>) -> Result<Duration, Error> {
+    let seconds = grace_period_seconds.unwrap_or(DEFAULT_ROTATION_GRACE_PERIOD_SECONDS);
+    if seconds > MAX_ROTATION_GRACE_PERIOD_SECONDS {
+        tracing::info!("Rotation grace period too long: {}s", seconds);
+        return Err(Error::BadRequest);
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// The user acting through `credential`, `None` for services, which are
+/// trusted to have authenticated the users they act for.
+fn credential_user(credential: &Credential) -> Result<Option<Uuid>, Error> {
+    match credential {
+        Credential::ApiKey { .. } => Ok(None),
+        Credential::Client(client) => Ok(Some(client.user_id)),
+        Credential::Jwt(claims) => Uuid::parse_str(&claims.sub).map(Some).map_err(|_| {
+            tracing::info!("JWT subject is not a user id: {}", claims.sub);
+            Error::Forbidden
+        }),
+    }
+}
+
+/// Services may read any user, users only themselves.
+pub fn check_user_access(credential: &Credential, user_id: Uuid) -> Result<(), Error> {
+    match credential_user(credential)? {
+        Some(caller) if caller != user_id => {
+            tracing::info!("User {} may not read user {}", caller, user_id);
+            Err(Error::Forbidden)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Who a client created with `credential` belongs to. Client tokens act for
+/// their owner, so letting callers pick any `user_id` would let them act as
+/// anyone.
+pub fn client_owner(credential: &Credential, user_id: Option<Uuid>) -> Result<Uuid, Error> {
+    let Some(owner) = credential_user(credential)? else {
+        return user_id.ok_or(Error::BadRequest);
+    };
+    match user_id {
+        Some(user_id) if user_id != owner => {
+            tracing::info!("User {} may not create clients for {}", owner, user_id);
+            Err(Error::Forbidden)
+        }
+        _ => Ok(owner),
+    }
+}
+
+/// `Error::BadRequest` for unknown scopes. `scope::ROLES_WRITE` is for
+/// admins only, as `set_role` trusts clients that have it.
+pub async fn check_client_scopes<R: Repositories>(
+    repo: &R,
+    owner: Uuid,
+    scopes: &[String],
+) -> Result<(), Error> {
+    if let Some(unknown) = scopes.iter().find(|s| !scope::is_known(s)) {
+        tracing::info!("Unknown client scope: {}", unknown);
+        return Err(Error::BadRequest);
+    }
+    if scopes.iter().any(|s| s == scope::ROLES_WRITE) {
+        let roles = repo.user().get_roles(owner).await?;
+        if !roles.iter().any(|r| r == permission::ADMIN) {
+            tracing::info!(
+                "User {} may not create clients with {}",
+                owner,
+                scope::ROLES_WRITE
+            );
+            return Err(Error::Forbidden);
+        }
+    }
+    Ok(())
+}
+
+/// When a client created at `now` expires, `Error::BadRequest` past
+/// `MAX_CLIENT_EXPIRY_SECONDS`.
+pub fn client_expires_at(
+    now: DateTime<Utc>,
+    expires_in_seconds: Option<u64>,
+) -> Result<Option<DateTime<Utc>>, Error> {
+    let Some(seconds) = expires_in_seconds else {
+        return Ok(None);
+    };
+    if seconds > MAX_CLIENT_EXPIRY_SECONDS {
+        tracing::info!("Client expiry too far in the future: {}s", seconds);
+        return Err(Error::BadRequest);
+    }
+    // in range for `Duration::seconds` after the check above
+    now.checked_add_signed(chrono::Duration::seconds(seconds as i64))
+        .map(Some)
+        .ok_or(Error::BadRequest)
+}
+
+/// Grants or revokes `role` on behalf of `caller`, who must be an admin.
+pub async fn set_role<R: Repositories>(
+    repo: &R,
+    caller: Uuid,
+    user_id: Uuid,
+    role: &str,
+    granted: bool,
+) -> Result<UserPermissionsModel, Error> {
+    if !permission::is_known_role(role) {
+        tracing::info!("Unknown role: {}", role);
+        return Err(Error::BadRequest);
+    }
+    let caller_roles = repo.user().get_roles(caller).await?;
+    if !caller_roles.iter().any(|r| r == permission::ADMIN) {
+        tracing::info!("User {} may not change roles", caller);
+        return Err(Error::Forbidden);
+    }
+    if granted {
+        repo.user().grant_role(user_id, role).await?;
+    } else {
+        repo.user().revoke_role(user_id, role).await?;
+    }
+    tracing::info!(
+        "User {} {} role {} of user {}",
+        caller,
+        if granted { "granted" } else { "revoked" },
+        role,
+        user_id
+    );
+    permissions(repo, user_id).await
+}
+
+fn check_new_password(password: &Secret<String>) -> Result<(), Error> {
+    if password.expose_secret().chars().count() < MIN_PASSWORD_LENGTH {
+        tracing::info!("New password is too short");
+        return Err(Error::BadRequest);
+    }
+    Ok(())
+}
+
+pub async fn change_password<R: Repositories>(
+    repo: &R,
+    user_id: Uuid,
+    current: Secret<String>,
+    new: Secret<String>,
+) -> Result<(), Error> {
+    check_new_password(&new)?;
+    repo.user().change_password(user_id, current, new).await
+}
+
+/// Sends the user named `name` a reset link. Unknown names are not an error,
+/// so callers can't tell which names exist.
+pub async fn request_password_reset<R: Repositories>(
+    repo: &R,
+    notifier: &dyn Notifier,
+    config: &PasswordResetConfig,
+    name: &str,
+) -> Result<(), Error> {
+    let (user, token) = match repo.user().create_password_reset(name, config.ttl).await? {
+        Some(reset) => reset,
+        None => {
+            tracing::info!("Password reset requested for an unknown user");
+            return Ok(());
+        }
+    };
+    let notification = Notification::PasswordReset {
+        link: config.link(&token),
+        expires_in: config.ttl,
+    };
+    notifier.notify(&user, &notification).await
+}
+
+pub async fn reset_password<R: Repositories>(
+    repo: &R,
+    token: Secret<String>,
+    new: Secret<String>,
+) -> Result<UserTransportModel, Error> {
+    check_new_password(&new)?;
+    repo.user().reset_password(token, new).await
+}
+
 /*
 pub async fn add<R: Repositories>(repo: Arc<R>, new_user: &NewUser) -> Result<UserId> {
     let user_id = repo.user().add(&new_user).await?;
@@ -21,7 +230,9 @@ pub async fn add<R: Repositories>(repo: Arc<R>, new_user: &NewUser) -> Result<Us
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::notifier::MockNotifier;
     use crate::tests::{fixtures::user_fixture, repositories::create_repositories_for_test};
+    use shared::jwt::Claims;
 
     #[tokio::test]
     async fn test_get_user() {
@@ -37,4 +248,213 @@ mod tests {
         let user = view(Arc::new(mock_repo_impl), user_id).await.unwrap();
         assert_eq!(user, user_fixture(user_id));
     }
+
+    #[test]
+    fn test_client_expires_at() {
+        let now = Utc::now();
+        assert!(matches!(client_expires_at(now, None), Ok(None)));
+        assert_eq!(
+            client_expires_at(now, Some(60)).unwrap(),
+            Some(now + chrono::Duration::seconds(60))
+        );
+        assert!(client_expires_at(now, Some(MAX_CLIENT_EXPIRY_SECONDS)).is_ok());
+        assert!(matches!(
+            client_expires_at(now, Some(MAX_CLIENT_EXPIRY_SECONDS + 1)),
+            Err(Error::BadRequest)
+        ));
+        assert!(matches!(
+            client_expires_at(now, Some(u64::MAX)),
+            Err(Error::BadRequest)
+        ));
+    }
+
+    #[test]
+    fn test_client_owner() {
+        let user_id = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let service = Credential::ApiKey {
+            name: "website".to_string(),
+        };
+        let jwt = |sub: &str| {
+            Credential::Jwt(Claims {
+                sub: sub.to_string(),
+                exp: 0,
+                iss: None,
+                extra: Default::default(),
+            })
+        };
+
+        assert_eq!(client_owner(&service, Some(user_id)).unwrap(), user_id);
+        assert!(matches!(
+            client_owner(&service, None),
+            Err(Error::BadRequest)
+        ));
+        let user = jwt(&user_id.to_string());
+        assert_eq!(client_owner(&user, None).unwrap(), user_id);
+        assert_eq!(client_owner(&user, Some(user_id)).unwrap(), user_id);
+        assert!(matches!(
+            client_owner(&user, Some(other)),
+            Err(Error::Forbidden)
+        ));
+        assert!(matches!(
+            client_owner(&jwt("alice"), None),
+            Err(Error::Forbidden)
+        ));
+    }
+
+    #[test]
+    fn test_rotation_grace_period() {
+        assert_eq!(
+            rotation_grace_period(None).unwrap(),
+            Duration::from_secs(DEFAULT_ROTATION_GRACE_PERIOD_SECONDS)
+        );
+        assert_eq!(rotation_grace_period(Some(0)).unwrap(), Duration::ZERO);
+        assert!(rotation_grace_period(Some(MAX_ROTATION_GRACE_PERIOD_SECONDS)).is_ok());
+        assert!(matches!(
+            rotation_grace_period(Some(MAX_ROTATION_GRACE_PERIOD_SECONDS + 1)),
+            Err(Error::BadRequest)
+        ));
+        assert!(matches!(
+            rotation_grace_period(Some(u64::MAX)),
+            Err(Error::BadRequest)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_check_client_scopes() {
+        let admin = Uuid::new_v4();
+        let user_id = Uuid::new_v4();
+        let scopes = |scopes: &[&str]| scopes.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let mut mock_repo_impl = create_repositories_for_test().await;
+        mock_repo_impl
+            .user
+            .expect_get_roles()
+            .returning(move |id| match id == admin {
+                true => Ok(vec![permission::ADMIN.to_string()]),
+                false => Ok(vec![]),
+            });
+
+        let default = scopes(scope::DEFAULT);
+        assert!(check_client_scopes(&mock_repo_impl, user_id, &default)
+            .await
+            .is_ok());
+        let res = check_client_scopes(&mock_repo_impl, user_id, &scopes(&["accounts:sudo"])).await;
+        assert!(matches!(res, Err(Error::BadRequest)));
+        let roles_write = scopes(&[scope::ROLES_WRITE]);
+        let res = check_client_scopes(&mock_repo_impl, user_id, &roles_write).await;
+        assert!(matches!(res, Err(Error::Forbidden)));
+        assert!(check_client_scopes(&mock_repo_impl, admin, &roles_write)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_set_role() {
+        let admin = Uuid::new_v4();
+        let user_id = Uuid::new_v4();
+
+        // the user's roles, as granted through the mock
+        let granted = Arc::new(std::sync::Mutex::new(Vec::<String>::new()));
+
+        let mut mock_repo_impl = create_repositories_for_test().await;
+        let roles = granted.clone();
+        mock_repo_impl
+            .user
+            .expect_get_roles()
+            .returning(move |id| match id == admin {
+                true => Ok(vec![permission::ADMIN.to_string()]),
+                false => Ok(roles.lock().unwrap().clone()),
+            });
+        let roles = granted.clone();
+        mock_repo_impl
+            .user
+            .expect_grant_role()
+            .withf(move |id, role| *id == user_id && role == permission::ADMIN)
+            .times(1)
+            .returning(move |_, role| {
+                roles.lock().unwrap().push(role.to_string());
+                Ok(())
+            });
+
+        let res = set_role(&mock_repo_impl, user_id, admin, permission::ADMIN, false).await;
+        assert!(matches!(res, Err(Error::Forbidden)));
+        let res = set_role(&mock_repo_impl, admin, user_id, "owner", true).await;
+        assert!(matches!(res, Err(Error::BadRequest)));
+
+        let permissions = set_role(&mock_repo_impl, admin, user_id, permission::ADMIN, true)
+            .await
+            .unwrap();
+        assert_eq!(permissions.user_id, user_id);
+        assert_eq!(permissions.roles, vec![permission::ADMIN.to_string()]);
+        assert_eq!(
+            permissions.permissions,
+            vec![
+                permission::ADMIN_VIEW.to_string(),
+                permission::CATEGORY_VIEW.to_string()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_change_password_checks_length() {
+        let user_id = Uuid::new_v4();
+        let mut mock_repo_impl = create_repositories_for_test().await;
+        mock_repo_impl
+            .user
+            .expect_change_password()
+            .withf(move |id, _, new| *id == user_id && new.expose_secret() == "long enough")
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let secret = |s: &str| Secret::new(s.to_string());
+        let res = change_password(&mock_repo_impl, user_id, secret("old"), secret("short")).await;
+        assert!(matches!(res, Err(Error::BadRequest)));
+        change_password(
+            &mock_repo_impl,
+            user_id,
+            secret("old"),
+            secret("long enough"),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_request_password_reset() {
+        let user_id = Uuid::new_v4();
+        let config = PasswordResetConfig {
+            url: "http://localhost:8000/password/reset".to_string(),
+            ttl: Duration::from_secs(600),
+        };
+
+        let mut mock_repo_impl = create_repositories_for_test().await;
+        mock_repo_impl
+            .user
+            .expect_create_password_reset()
+            .returning(move |name, _| match name {
+                "alice" => Ok(Some((
+                    user_fixture(user_id),
+                    Secret::new("t0k3n".to_string()),
+                ))),
+                _ => Ok(None),
+            });
+        let mut notifier = MockNotifier::new();
+        notifier
+            .expect_notify()
+            .withf(move |user, notification| {
+                let Notification::PasswordReset { link, .. } = notification;
+                user.id == user_id
+                    && link.expose_secret() == "http://localhost:8000/password/reset?token=t0k3n"
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        request_password_reset(&mock_repo_impl, &notifier, &config, "alice")
+            .await
+            .unwrap();
+        request_password_reset(&mock_repo_impl, &notifier, &config, "nobody")
+            .await
+            .unwrap();
+    }
 }
@@ -1,16 +1,23 @@
-use crate::db_init::Db;
+use crate::{
+    db_init::Db,
+    openfga_outbox,
+    password::{generate_reset_token, hash_reset_token, PasswordHashing},
+};
 use anyhow::Context;
-use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use axum::async_trait;
+use chrono::Utc;
 use mockall::automock;
-use secrecy::{ExposeSecret, Secret};
+use secrecy::Secret;
 use shared::{
     error::Error,
     model::{UserModel, UserTransportModel},
-    schema::LoginPayload,
+    openfga::make_tuple,
+    schema::{LinkIdentity, LoginPayload},
     tracing::make_otel_db_span,
 };
-use sqlx::Execute;
+use sqlx::{Execute, PgConnection};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::task;
 use tracing::{self, Instrument};
 use uuid::Uuid;
@@ -18,10 +25,75 @@ use uuid::Uuid;
 #[derive(Debug, Clone)]
 pub struct UserRepoImpl {
     pool: Db,
+    hashing: Arc<PasswordHashing>,
 }
 impl UserRepoImpl {
-    pub fn new(pool: Db) -> Self {
-        Self { pool }
+    pub fn new(pool: Db, hashing: PasswordHashing) -> Self {
+        Self {
+            pool,
+            hashing: Arc::new(hashing),
+        }
+    }
+
+    /// Replaces a password hash made with weaker parameters, unless the
+    /// password was changed meanwhile.
+    async fn upgrade_password_hash(
+        &self,
+        user_id: Uuid,
+        old_hash: &str,
+        new_hash: &str,
+    ) -> Result<(), Error> {
+        let query = sqlx::query!(
+            "UPDATE users SET password_hash = $3 WHERE id = $1 AND password_hash = $2",
+            user_id,
+            old_hash,
+            new_hash,
+        );
+        let sql = query.sql().clone();
+        query
+            .execute(&*self.pool)
+            .instrument(make_otel_db_span("UPDATE", sql))
+            .await?;
+        tracing::info!("Upgraded the password hash of user {}", user_id);
+        Ok(())
+    }
+
+    /// The user an already linked identity belongs to, recording the login.
+    async fn login_identity(
+        &self,
+        identity: &LinkIdentity,
+    ) -> Result<Option<UserTransportModel>, Error> {
+        let query = sqlx::query_as!(
+            UserTransportModel,
+            r#"
+        UPDATE identities
+        SET last_login_at = now(), email = COALESCE($3, identities.email)
+        FROM users
+        WHERE identities.provider = $1 AND identities.subject = $2 AND users.id = identities.user_id
+        RETURNING users.id, users.name
+        "#,
+            identity.provider,
+            identity.subject,
+            identity.email,
+        );
+        let sql = query.sql().clone();
+        let user = query
+            .fetch_optional(&*self.pool)
+            .instrument(make_otel_db_span("UPDATE", sql))
+            .await?;
+        match (user, identity.user_id) {
+            (Some(user), Some(user_id)) if user.id != user_id => {
+                tracing::info!(
+                    "Identity from {} is already linked to user {}",
+                    identity.provider,
+                    user.id
+                );
+                Err(Error::Conflict(
+                    "identity is linked to another user".to_string(),
+                ))
+            }
+            (user, _) => Ok(user),
+        }
     }
 }
 
@@ -34,6 +106,41 @@ pub trait UserRepo {
         &self,
         credentials: LoginPayload,
     ) -> Result<UserTransportModel, Error>;
+    /// The user of an identity from an OpenID Connect provider. Unknown
+    /// identities are linked to `identity.user_id`, or to a new user without
+    /// a password.
+    async fn get_or_create_identity_user(
+        &self,
+        identity: LinkIdentity,
+    ) -> Result<UserTransportModel, Error>;
+    /// The roles granted to the user, sorted.
+    async fn get_roles(&self, user_id: Uuid) -> Result<Vec<String>, Error>;
+    /// Granting a role twice is a no-op. `Error::NotFound` if there is no
+    /// such user.
+    async fn grant_role(&self, user_id: Uuid, role: &str) -> Result<(), Error>;
+    async fn revoke_role(&self, user_id: Uuid, role: &str) -> Result<(), Error>;
+    /// `Error::Unauthorized` if `current` is wrong, or there is no such user
+    /// or it has no password. Outstanding reset tokens stop working.
+    async fn change_password(
+        &self,
+        user_id: Uuid,
+        current: Secret<String>,
+        new: Secret<String>,
+    ) -> Result<(), Error>;
+    /// A new reset token for the user named `name`, working for `ttl`, or
+    /// `None` if there is no such user. Only the token's hash is stored.
+    async fn create_password_reset(
+        &self,
+        name: &str,
+        ttl: Duration,
+    ) -> Result<Option<(UserTransportModel, Secret<String>)>, Error>;
+    /// Sets the password of the token's user, using up all their tokens.
+    /// `Error::Unauthorized` if the token is unknown, used or expired.
+    async fn reset_password(
+        &self,
+        token: Secret<String>,
+        new: Secret<String>,
+    ) -> Result<UserTransportModel, Error>;
 }
 
 #[async_trait]
@@ -53,37 +160,124 @@ impl UserRepo for UserRepoImpl {
         );
         let sql = query.sql().clone();
         let user = query
-            .fetch_one(&*self.pool)
+            .fetch_optional(&*self.pool)
             .instrument(make_otel_db_span("SELECT", sql))
-            .await
-            .map_err(|_| Error::Unauthorized)?;
+            .await?;
+        // Without a password to check, verify against a dummy hash anyway so
+        // unknown names take as long to reject as wrong passwords.
+        let (user, password_hash) = match user {
+            Some(UserModel {
+                id,
+                name,
+                password_hash: Some(password_hash),
+            }) => (Some(UserTransportModel { id, name }), password_hash),
+            Some(user) => {
+                tracing::info!("User {} has no password", user.id);
+                (None, self.hashing.dummy_hash())
+            }
+            None => (None, self.hashing.dummy_hash()),
+        };
 
-        task::spawn_blocking(move || {
-            verify_password_hash(user.password_hash, credentials.password)
+        let hashing = self.hashing.clone();
+        let (password_hash, verified) = task::spawn_blocking(move || {
+            let verified = hashing
+                .verify(&password_hash, &credentials.password)
+                .map(|needs_rehash| needs_rehash.then(|| hashing.hash(&credentials.password)));
+            (password_hash, verified)
         })
         .await
         .context("Failed to spawn blocking task.")
-        .map_err(|_| Error::InternalServerError)??;
-
-        let return_user = UserTransportModel {
-            id: user.id,
-            name: user.name,
-        };
+        .map_err(|_| Error::InternalServerError)?;
 
-        Ok(return_user)
+        match (user, verified) {
+            (Some(user), Ok(new_hash)) => {
+                if let Some(new_hash) = new_hash {
+                    // the login succeeded either way
+                    if let Err(e) = self
+                        .upgrade_password_hash(user.id, &password_hash, &new_hash)
+                        .await
+                    {
+                        tracing::error!("Failed to upgrade a password hash: {:?}", e);
+                    }
+                }
+                Ok(user)
+            }
+            (_, Err(Error::InternalServerError)) => Err(Error::InternalServerError),
+            _ => Err(Error::Unauthorized),
+        }
     }
     async fn create_user(&self, credentials: LoginPayload) -> Result<UserTransportModel, Error> {
-        let password_hash = generate_hash(&credentials).await;
+        let hashing = self.hashing.clone();
+        let password = credentials.password;
+        let password_hash = task::spawn_blocking(move || hashing.hash(&password))
+            .await
+            .context("Failed to spawn blocking task.")?;
 
-        let user = sqlx::query_as!(
-            UserTransportModel,
-            "INSERT INTO users(name, password_hash) VALUES ($1, $2) RETURNING id, name",
-            credentials.name,
-            password_hash
-        )
-        .fetch_one(&*self.pool)
-        .await
-        .context("Failed to create user.")?;
+        let mut tx = self.pool.begin().await?;
+        let user = insert_user(&mut tx, &credentials.name, Some(password_hash)).await?;
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    async fn get_or_create_identity_user(
+        &self,
+        identity: LinkIdentity,
+    ) -> Result<UserTransportModel, Error> {
+        if let Some(user) = self.login_identity(&identity).await? {
+            return Ok(user);
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let user = match identity.user_id {
+            Some(user_id) => {
+                sqlx::query_as!(
+                    UserTransportModel,
+                    "SELECT id, name FROM users WHERE id = $1",
+                    user_id
+                )
+                .fetch_one(&mut *tx)
+                .await?
+            }
+            None => {
+                let name = identity.name.as_ref().or(identity.email.as_ref());
+                let name = name
+                    .cloned()
+                    .unwrap_or_else(|| format!("{}:{}", identity.provider, identity.subject));
+                insert_user(&mut tx, &name, None).await?
+            }
+        };
+        let query = sqlx::query_scalar!(
+            r#"
+        INSERT INTO identities (provider, subject, user_id, email)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (provider, subject) DO NOTHING
+        RETURNING user_id
+        "#,
+            identity.provider,
+            identity.subject,
+            user.id,
+            identity.email,
+        );
+        let sql = query.sql().clone();
+        let linked = query
+            .fetch_optional(&mut *tx)
+            .instrument(make_otel_db_span("INSERT", sql))
+            .await?;
+        if linked.is_none() {
+            // linked by a concurrent login, use that one
+            tx.rollback().await?;
+            return self
+                .login_identity(&identity)
+                .await?
+                .ok_or(Error::InternalServerError);
+        }
+        tx.commit().await?;
+        tracing::info!(
+            "Linked identity from {} to user {}",
+            identity.provider,
+            user.id
+        );
 
         Ok(user)
     }
@@ -102,35 +296,230 @@ impl UserRepo for UserRepoImpl {
             .context("Failed to get user.")?;
         Ok(user)
     }
-}
 
-async fn generate_hash(credentials: &LoginPayload) -> String {
-    let salt = SaltString::generate(&mut rand::thread_rng());
-    let password_hash = Argon2::default()
-        .hash_password(credentials.password.expose_secret().as_bytes(), &salt)
-        .unwrap()
-        .to_string();
+    async fn get_roles(&self, user_id: Uuid) -> Result<Vec<String>, Error> {
+        let query = sqlx::query_scalar!(
+            "SELECT role FROM user_roles WHERE user_id = $1 ORDER BY role",
+            user_id
+        );
+        let sql = query.sql().clone();
+        let roles = query
+            .fetch_all(&*self.pool)
+            .instrument(make_otel_db_span("SELECT", sql))
+            .await?;
+        Ok(roles)
+    }
+
+    async fn grant_role(&self, user_id: Uuid, role: &str) -> Result<(), Error> {
+        let query = sqlx::query!(
+            r#"
+        INSERT INTO user_roles (user_id, role)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id, role) DO NOTHING
+        "#,
+            user_id,
+            role,
+        );
+        let sql = query.sql().clone();
+        query
+            .execute(&*self.pool)
+            .instrument(make_otel_db_span("INSERT", sql))
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(e) if e.is_foreign_key_violation() => Error::NotFound,
+                e => e.into(),
+            })?;
+        Ok(())
+    }
 
-    password_hash
-}
+    async fn revoke_role(&self, user_id: Uuid, role: &str) -> Result<(), Error> {
+        let query = sqlx::query!(
+            "DELETE FROM user_roles WHERE user_id = $1 AND role = $2",
+            user_id,
+            role,
+        );
+        let sql = query.sql().clone();
+        query
+            .execute(&*self.pool)
+            .instrument(make_otel_db_span("DELETE", sql))
+            .await?;
+        Ok(())
+    }
 
-#[tracing::instrument(
-    name = "Verify password hash",
-    skip(expected_password_hash, password_candidate)
-)]
-fn verify_password_hash(
-    expected_password_hash: String,
-    password_candidate: Secret<String>,
-) -> Result<(), Error> {
-    let expected_password_hash = PasswordHash::new(&expected_password_hash)
-        .context("Failed to parse hash in PHC string format.")
-        .map_err(|_| Error::InternalServerError)?;
+    async fn change_password(
+        &self,
+        user_id: Uuid,
+        current: Secret<String>,
+        new: Secret<String>,
+    ) -> Result<(), Error> {
+        let query = sqlx::query_scalar!("SELECT password_hash FROM users WHERE id = $1", user_id);
+        let sql = query.sql().clone();
+        let password_hash = query
+            .fetch_optional(&*self.pool)
+            .instrument(make_otel_db_span("SELECT", sql))
+            .await?;
+        // Like `validate_credentials`, unknown users and users without a
+        // password take as long to reject as wrong passwords.
+        let password_hash = match password_hash {
+            Some(Some(password_hash)) => Some(password_hash),
+            Some(None) => {
+                tracing::info!("User {} has no password to change", user_id);
+                None
+            }
+            None => None,
+        };
+
+        let hashing = self.hashing.clone();
+        let (password_hash, new_hash) = task::spawn_blocking(move || {
+            let expected_hash = password_hash
+                .clone()
+                .unwrap_or_else(|| hashing.dummy_hash());
+            hashing
+                .verify(&expected_hash, &current)
+                .map(|_| (password_hash, hashing.hash(&new)))
+        })
+        .await
+        .context("Failed to spawn blocking task.")
+        .map_err(|_| Error::InternalServerError)??;
+        // the dummy hash is of a random password, but to be sure
+        let password_hash = password_hash.ok_or(Error::Unauthorized)?;
 
-    Argon2::default()
-        .verify_password(
-            password_candidate.expose_secret().as_bytes(),
-            &expected_password_hash,
+        let mut tx = self.pool.begin().await?;
+        // unless the password was changed meanwhile
+        let query = sqlx::query!(
+            "UPDATE users SET password_hash = $3 WHERE id = $1 AND password_hash = $2",
+            user_id,
+            password_hash,
+            new_hash,
+        );
+        let sql = query.sql().clone();
+        let updated = query
+            .execute(&mut *tx)
+            .instrument(make_otel_db_span("UPDATE", sql))
+            .await?;
+        if updated.rows_affected() == 0 {
+            return Err(Error::Unauthorized);
+        }
+        use_up_reset_tokens(&mut tx, user_id).await?;
+        tx.commit().await?;
+        tracing::info!(target: "audit", event = "password_changed", %user_id, "Password changed");
+        Ok(())
+    }
+
+    async fn create_password_reset(
+        &self,
+        name: &str,
+        ttl: Duration,
+    ) -> Result<Option<(UserTransportModel, Secret<String>)>, Error> {
+        let token = generate_reset_token();
+        let expires_at = Utc::now() + chrono::Duration::from_std(ttl).context("TTL too long.")?;
+        let query = sqlx::query_as!(
+            UserTransportModel,
+            r#"
+        WITH token AS (
+            INSERT INTO password_reset_tokens (user_id, token_hash, expires_at)
+            SELECT id, $2, $3 FROM users WHERE name = $1 LIMIT 1
+            RETURNING user_id
         )
-        .context("Invalid password.")
-        .map_err(|_| Error::Unauthorized)
+        SELECT users.id, users.name FROM token JOIN users ON users.id = token.user_id
+        "#,
+            name,
+            hash_reset_token(&token),
+            expires_at,
+        );
+        let sql = query.sql().clone();
+        let user = query
+            .fetch_optional(&*self.pool)
+            .instrument(make_otel_db_span("INSERT", sql))
+            .await?;
+        if let Some(user) = &user {
+            tracing::info!(
+                target: "audit",
+                event = "password_reset_requested",
+                user_id = %user.id,
+                "Password reset requested"
+            );
+        }
+        Ok(user.map(|user| (user, token)))
+    }
+
+    async fn reset_password(
+        &self,
+        token: Secret<String>,
+        new: Secret<String>,
+    ) -> Result<UserTransportModel, Error> {
+        let mut tx = self.pool.begin().await?;
+        let query = sqlx::query_scalar!(
+            r#"
+        UPDATE password_reset_tokens SET used_at = now()
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+        RETURNING user_id
+        "#,
+            hash_reset_token(&token),
+        );
+        let sql = query.sql().clone();
+        let user_id = query
+            .fetch_optional(&mut *tx)
+            .instrument(make_otel_db_span("UPDATE", sql))
+            .await?
+            .ok_or_else(|| {
+                tracing::info!("Unknown, used or expired password reset token");
+                Error::Unauthorized
+            })?;
+        // only for valid tokens, hashing is expensive; the token stays
+        // claimed by our transaction meanwhile
+        let hashing = self.hashing.clone();
+        let new_hash = task::spawn_blocking(move || hashing.hash(&new))
+            .await
+            .context("Failed to spawn blocking task.")?;
+        let query = sqlx::query_as!(
+            UserTransportModel,
+            "UPDATE users SET password_hash = $2 WHERE id = $1 RETURNING id, name",
+            user_id,
+            new_hash,
+        );
+        let sql = query.sql().clone();
+        let user = query
+            .fetch_one(&mut *tx)
+            .instrument(make_otel_db_span("UPDATE", sql))
+            .await?;
+        use_up_reset_tokens(&mut tx, user_id).await?;
+        tx.commit().await?;
+        tracing::info!(target: "audit", event = "password_reset", %user_id, "Password reset");
+        Ok(user)
+    }
+}
+
+/// Once the password is changed, older reset links must not work.
+async fn use_up_reset_tokens(tx: &mut PgConnection, user_id: Uuid) -> Result<(), Error> {
+    let query = sqlx::query!(
+        "UPDATE password_reset_tokens SET used_at = now() WHERE user_id = $1 AND used_at IS NULL",
+        user_id
+    );
+    let sql = query.sql().clone();
+    query
+        .execute(&mut *tx)
+        .instrument(make_otel_db_span("UPDATE", sql))
+        .await?;
+    Ok(())
+}
+
+async fn insert_user(
+    tx: &mut PgConnection,
+    name: &str,
+    password_hash: Option<String>,
+) -> Result<UserTransportModel, Error> {
+    let user = sqlx::query_as!(
+        UserTransportModel,
+        "INSERT INTO users(name, password_hash) VALUES ($1, $2) RETURNING id, name",
+        name,
+        password_hash
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .context("Failed to create user.")?;
+
+    let fga_user = format!("user:{}", user.id);
+    openfga_outbox::enqueue_write(tx, make_tuple(&fga_user, "owner", &fga_user)).await?;
+    Ok(user)
 }
@@ -1,15 +1,24 @@
+use crate::credential::{Credential, CredentialValidator};
 use crate::error::Error;
+use crate::token_cache::{CachedToken, TokenCache};
 use crate::{client::get_client_by_token, model::ClientModel};
 use axum::{
-    extract::TypedHeader,
+    extract::{State, TypedHeader},
     headers::authorization::{Authorization, Bearer},
     http::Request,
-    http::StatusCode,
+    http::{Method, StatusCode},
     middleware::Next,
     response::Response,
 };
+use chrono::Utc;
+use std::sync::{Arc, OnceLock};
 
+/// Authenticates the bearer credential with the router's validator.
+///
+/// Inserts the resulting [`Credential`], the caller's `Principal` when there
+/// is one, and for client tokens the `ClientModel` used by the scope guards.
 pub async fn auth<B>(
+    State(validator): State<Arc<dyn CredentialValidator>>,
     // run the `TypedHeader` extractor
     TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
     // you can also add more extractors here but the last
@@ -18,44 +27,298 @@ pub async fn auth<B>(
     request: Request<B>,
     next: Next<B>,
 ) -> Result<Response, Error> {
-    if token_is_valid(auth.token()) {
-        let response = next.run(request).await;
-        Ok(response)
-    } else {
-        Err(Error::Unauthorized)
-    }
-}
-
-fn token_is_valid(token: &str) -> bool {
-    tracing::info!("token provided: {}", token);
-    return true;
+    authenticate(validator.as_ref(), auth.token(), request, next).await
 }
 
+/// [`auth`] with the [`ClientTokenValidator`].
 pub async fn token_auth<B>(
-    // run the `TypedHeader` extractor
     TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
-    // you can also add more extractors here but the last
-    // extractor must implement `FromRequest` which
-    // `Request` does
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, Error> {
+    authenticate(&ClientTokenValidator, auth.token(), request, next).await
+}
+
+async fn authenticate<B>(
+    validator: &dyn CredentialValidator,
+    token: &str,
     mut request: Request<B>,
     next: Next<B>,
 ) -> Result<Response, Error> {
-    match is_valid_client_token(auth.token()).await {
+    let credential = validator.validate(token).await.map_err(|e| {
+        tracing::info!("Credential rejected by {:?}: {}", validator, e);
+        match e {
+            // tells the caller why, e.g. that their token expired
+            Error::Auth(e) => Error::Auth(e),
+            _ => Error::Unauthorized,
+        }
+    })?;
+    let extensions = request.extensions_mut();
+    if let Some(principal) = credential.principal() {
+        extensions.insert(principal);
+    }
+    if let Credential::Client(client) = &credential {
+        extensions.insert(client.clone());
+    }
+    extensions.insert(credential);
+    Ok(next.run(request).await)
+}
+
+/// Client tokens, validated by the api-server and cached in [`token_cache`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClientTokenValidator;
+
+#[axum::async_trait]
+impl CredentialValidator for ClientTokenValidator {
+    async fn validate(&self, credential: &str) -> Result<Credential, Error> {
+        let client = is_valid_client_token(credential).await?;
+        if !client.is_active(Utc::now()) {
+            tracing::info!("Client {} is revoked or expired", client.id);
+            return Err(Error::Unauthorized);
+        }
+        Ok(Credential::Client(client))
+    }
+}
+
+async fn is_valid_client_token(token: &str) -> Result<ClientModel, Error> {
+    let cache = token_cache();
+    match cache.get(token) {
+        Some(CachedToken::Valid(client)) => return Ok(client),
+        Some(CachedToken::Invalid) => return Err(Error::Unauthorized),
+        None => {}
+    }
+
+    match get_client_by_token(token.to_string(), None).await {
         Ok(client) => {
-            request.extensions_mut().insert(client);
-            let response = next.run(request).await;
-            Ok(response)
+            cache.insert_valid(token, client.clone());
+            Ok(client)
         }
-        Err(e) => {
-            tracing::error!("Error: {}", e);
+        // only cache definite rejections, not failures to reach the api-server
+        Err(Error::Unauthorized) => {
+            cache.insert_invalid(token);
             Err(Error::Unauthorized)
         }
+        Err(e) => Err(e),
     }
 }
 
-async fn is_valid_client_token(token: &str) -> Result<ClientModel, Error> {
-    tracing::info!("token provided: {}", token);
-    let client = get_client_by_token(token.to_string(), None).await?;
+/// The cache `token_auth` keeps validated tokens in, configured with
+/// `TokenCache::from_env`. Evict clients revoked or rotated through this
+/// process; the TTL bounds how long other revocations take to reach it.
+pub fn token_cache() -> &'static TokenCache {
+    static TOKEN_CACHE: OnceLock<TokenCache> = OnceLock::new();
+    TOKEN_CACHE.get_or_init(TokenCache::from_env)
+}
+
+/// Route-level scope requirement for token-authenticated requests, checked
+/// against the `ClientModel` inserted by `token_auth`.
+///
+/// ```ignore
+/// .route(
+///     "/api/accounts/:id",
+///     get(handler::get_account).route_layer(middleware::from_fn_with_state(
+///         RequireScope(scope::ACCOUNTS_READ),
+///         auth::require_scope,
+///     )),
+/// )
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RequireScope(pub &'static str);
+
+pub async fn require_scope<B>(
+    State(RequireScope(scope)): State<RequireScope>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, Error> {
+    check_scope(&request, scope)?;
+    Ok(next.run(request).await)
+}
+
+/// Scopes for a whole router keyed by path prefix, for routers such as the
+/// website proxy where one handler serves many API paths. Safe methods need
+/// the read scope, everything else the write scope. Paths without a rule
+/// need no scope.
+#[derive(Debug, Clone, Default)]
+pub struct ScopeRules {
+    rules: Vec<ScopeRule>,
+}
 
-    Ok(client)
+#[derive(Debug, Clone)]
+struct ScopeRule {
+    path_prefix: &'static str,
+    read: Option<&'static str>,
+    write: Option<&'static str>,
+}
+
+impl ScopeRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rule(
+        mut self,
+        path_prefix: &'static str,
+        read: Option<&'static str>,
+        write: Option<&'static str>,
+    ) -> Self {
+        self.rules.push(ScopeRule {
+            path_prefix,
+            read,
+            write,
+        });
+        self
+    }
+
+    fn required_scope(&self, method: &Method, path: &str) -> Option<&'static str> {
+        let rule = self.rules.iter().find(|rule| {
+            path.strip_prefix(rule.path_prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })?;
+        if method.is_safe() {
+            rule.read
+        } else {
+            rule.write
+        }
+    }
+}
+
+pub async fn require_scopes<B>(
+    State(rules): State<ScopeRules>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, Error> {
+    if let Some(scope) = rules.required_scope(request.method(), request.uri().path()) {
+        check_scope(&request, scope)?;
+    }
+    Ok(next.run(request).await)
+}
+
+fn check_scope<B>(request: &Request<B>, scope: &str) -> Result<(), Error> {
+    let client = request
+        .extensions()
+        .get::<ClientModel>()
+        .ok_or(Error::Unauthorized)?;
+    if client.has_scope(scope) {
+        Ok(())
+    } else {
+        tracing::info!("Client {} lacks scope {}", client.id, scope);
+        Err(Error::MissingScope(scope.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::*;
+    use crate::scope;
+    use axum::{body::Body, middleware, routing::get, Extension, Router};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    fn client(scopes: &[&str]) -> ClientModel {
+        ClientModel {
+            id: Uuid::new_v4(),
+            name: "ci".to_string(),
+            user_id: Uuid::new_v4(),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            expires_at: None,
+            revoked_at: None,
+            last_used_at: None,
+        }
+    }
+
+    fn app(client: ClientModel) -> Router {
+        let rules = ScopeRules::new().rule(
+            "/api/accounts",
+            Some(scope::ACCOUNTS_READ),
+            Some(scope::ACCOUNTS_WRITE),
+        );
+        Router::new()
+            .route("/api/:path", get(|| async { "ok" }).post(|| async { "ok" }))
+            .route(
+                "/clients",
+                get(|| async { "ok" }).route_layer(middleware::from_fn_with_state(
+                    RequireScope(scope::CLIENTS_WRITE),
+                    require_scope,
+                )),
+            )
+            .route_layer(middleware::from_fn_with_state(rules, require_scopes))
+            .layer(Extension(client))
+    }
+
+    fn request(method: Method, uri: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_read_only_client() {
+        let app = app(client(&[scope::ACCOUNTS_READ]));
+
+        let res = app
+            .clone()
+            .oneshot(request(Method::GET, "/api/accounts"))
+            .await;
+        assert_eq!(res.unwrap().status(), StatusCode::OK);
+        let res = app
+            .clone()
+            .oneshot(request(Method::POST, "/api/accounts"))
+            .await;
+        assert_eq!(res.unwrap().status(), StatusCode::FORBIDDEN);
+        // no rule for this path
+        let res = app
+            .clone()
+            .oneshot(request(Method::POST, "/api/users"))
+            .await;
+        assert_eq!(res.unwrap().status(), StatusCode::OK);
+        let res = app.oneshot(request(Method::GET, "/clients")).await;
+        assert_eq!(res.unwrap().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_client_with_scope() {
+        let app = app(client(scope::ALL));
+
+        let res = app
+            .clone()
+            .oneshot(request(Method::POST, "/api/accounts"))
+            .await;
+        assert_eq!(res.unwrap().status(), StatusCode::OK);
+        let res = app.oneshot(request(Method::GET, "/clients")).await;
+        assert_eq!(res.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_auth_with_api_keys() {
+        let validator: Arc<dyn CredentialValidator> = Arc::new(
+            crate::credential::StaticApiKeyValidator::new().with_key("monitoring", "key-1"),
+        );
+        let app = Router::new()
+            .route(
+                "/",
+                get(|Extension(credential): Extension<Credential>| async move {
+                    match credential {
+                        Credential::ApiKey { name } if name == "monitoring" => StatusCode::OK,
+                        _ => StatusCode::INTERNAL_SERVER_ERROR,
+                    }
+                }),
+            )
+            .route_layer(middleware::from_fn_with_state(validator, auth));
+        let with_token = |token: &str| {
+            Request::builder()
+                .uri("/")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let res = app.clone().oneshot(with_token("key-1")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.clone().oneshot(with_token("key-2")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = app.oneshot(request(Method::GET, "/")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
 }
@@ -0,0 +1,162 @@
+use crate::error::Error;
+use crate::model::ClientModel;
+use crate::openfga::{make_tuple, Authorizer};
+use axum::{
+    extract::{Path, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// The authenticated caller as an OpenFGA user, e.g. `user:<uuid>`.
+///
+/// Insert it into the request extensions from an authentication middleware;
+/// [`require_relation`] also understands the `ClientModel` inserted by
+/// `token_auth`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal(pub String);
+
+impl Principal {
+    pub fn user(id: Uuid) -> Self {
+        Principal(format!("user:{}", id))
+    }
+}
+
+/// Route-level authorization rule: the caller must have `relation` on the
+/// object `<object_type>:<value of path_param>`.
+///
+/// ```ignore
+/// .route(
+///     "/api/accounts/:id",
+///     get(handler::get_account).route_layer(middleware::from_fn_with_state(
+///         RequireRelation::new(openfga.clone(), "reader", "account", "id"),
+///         authz::require_relation,
+///     )),
+/// )
+/// ```
+#[derive(Debug, Clone)]
+pub struct RequireRelation {
+    openfga: Arc<dyn Authorizer>,
+    relation: String,
+    object_type: String,
+    path_param: String,
+}
+
+impl RequireRelation {
+    pub fn new(
+        openfga: Arc<dyn Authorizer>,
+        relation: &str,
+        object_type: &str,
+        path_param: &str,
+    ) -> Self {
+        Self {
+            openfga,
+            relation: relation.to_string(),
+            object_type: object_type.to_string(),
+            path_param: path_param.to_string(),
+        }
+    }
+}
+
+pub async fn require_relation<B>(
+    State(rule): State<RequireRelation>,
+    Path(params): Path<HashMap<String, String>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, Error> {
+    let principal = principal_from_request(&request).ok_or(Error::Unauthorized)?;
+    let id = params.get(&rule.path_param).ok_or_else(|| {
+        tracing::error!(
+            "Route has no path parameter '{}' to authorize against",
+            rule.path_param
+        );
+        Error::InternalServerError
+    })?;
+    let object = format!("{}:{}", rule.object_type, id);
+
+    let res = rule
+        .openfga
+        .check(make_tuple(&principal.0, &rule.relation, &object))
+        .await?;
+    if res.allowed {
+        Ok(next.run(request).await)
+    } else {
+        tracing::info!("{} is not {} of {}", principal.0, rule.relation, object);
+        Err(Error::Forbidden)
+    }
+}
+
+fn principal_from_request<B>(request: &Request<B>) -> Option<Principal> {
+    let extensions = request.extensions();
+    extensions.get::<Principal>().cloned().or_else(|| {
+        extensions
+            .get::<ClientModel>()
+            .map(|client| Principal::user(client.user_id))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authz::*;
+    use crate::openfga_memory::InMemoryOpenFga;
+    use crate::openfga_model::AuthorizationModel;
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    const MODEL: &str = "model
+  schema 1.1
+
+type user
+
+type repo
+  relations
+    define reader: [user]
+";
+
+    fn app(principal: Option<Principal>) -> Router {
+        let openfga = InMemoryOpenFga::new(AuthorizationModel::from_dsl(MODEL).unwrap())
+            .with_tuples([make_tuple("user:anne", "reader", "repo:api")]);
+        let router = Router::new().route(
+            "/repos/:id",
+            get(|| async { "ok" }).route_layer(middleware::from_fn_with_state(
+                RequireRelation::new(Arc::new(openfga), "reader", "repo", "id"),
+                require_relation,
+            )),
+        );
+        match principal {
+            Some(principal) => router.layer(Extension(principal)),
+            None => router,
+        }
+    }
+
+    fn request(uri: &str) -> Request<Body> {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_require_relation_allows() {
+        let app = app(Some(Principal("user:anne".to_string())));
+
+        let res = app.oneshot(request("/repos/api")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_require_relation_forbids() {
+        let app = app(Some(Principal("user:anne".to_string())));
+
+        let res = app.oneshot(request("/repos/infra")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_require_relation_without_principal() {
+        let app = app(None);
+
+        let res = app.oneshot(request("/repos/api")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -102,7 +102,10 @@ pub async fn auth_user(
         .json(&payload)
         .headers(headers.unwrap_or_default())
         .send();
-    let res = req.await.unwrap();
+    let res = req.await?;
+    if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Err(too_many_requests(&res));
+    }
     match res.json::<DataBody<model::UserTransportModel>>().await {
         Ok(user) => {
             println!("{:#?}", user);
@@ -115,6 +118,107 @@ pub async fn auth_user(
     }
 }
 
+fn too_many_requests(res: &reqwest::Response) -> Error {
+    let retry_after_seconds = res
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok()?.parse().ok())
+        .unwrap_or_default();
+    Error::TooManyRequests {
+        retry_after_seconds,
+    }
+}
+
+/// `Error::Unauthorized` for a wrong current password, `Error::BadRequest`
+/// for a too short new one.
+#[tracing::instrument]
+pub async fn change_password(
+    id: uuid::Uuid,
+    payload: schema::ChangePassword,
+    headers: Option<HeaderMap>,
+) -> Result<(), Error> {
+    let client = get_client();
+    let api_base_url = std::env::var("API_BASE_URL").expect("Define API_BASE_URL");
+
+    let res = client
+        .post(format!("{}/api/users/{}/password", api_base_url, id))
+        .json(&payload)
+        .headers(headers.unwrap_or_default())
+        .headers(get_trace_info())
+        .send()
+        .await?;
+    match res.status() {
+        status if status.is_success() => Ok(()),
+        reqwest::StatusCode::BAD_REQUEST => Err(Error::BadRequest),
+        reqwest::StatusCode::UNAUTHORIZED => Err(Error::Unauthorized),
+        reqwest::StatusCode::TOO_MANY_REQUESTS => Err(too_many_requests(&res)),
+        status => {
+            tracing::error!("api-server failed to change the password: {}", status);
+            Err(Error::InternalServerError)
+        }
+    }
+}
+
+/// Succeeds whether or not the user exists, `Error::TooManyRequests` once
+/// the client IP asked too often.
+#[tracing::instrument]
+pub async fn request_password_reset(
+    payload: schema::RequestPasswordReset,
+    headers: Option<HeaderMap>,
+) -> Result<(), Error> {
+    let client = get_client();
+    let api_base_url = std::env::var("API_BASE_URL").expect("Define API_BASE_URL");
+
+    let res = client
+        .post(format!("{}/api/users/password-reset", api_base_url))
+        .json(&payload)
+        .headers(headers.unwrap_or_default())
+        .headers(get_trace_info())
+        .send()
+        .await?;
+    match res.status() {
+        status if status.is_success() => Ok(()),
+        reqwest::StatusCode::TOO_MANY_REQUESTS => Err(too_many_requests(&res)),
+        status => {
+            tracing::error!("api-server failed to request a password reset: {}", status);
+            Err(Error::InternalServerError)
+        }
+    }
+}
+
+/// `Error::Unauthorized` for an unknown, used or expired token,
+/// `Error::BadRequest` for a too short password, `Error::TooManyRequests`
+/// after too many bad tokens from the client IP.
+#[tracing::instrument]
+pub async fn reset_password(
+    payload: schema::ResetPassword,
+    headers: Option<HeaderMap>,
+) -> Result<model::UserTransportModel, Error> {
+    let client = get_client();
+    let api_base_url = std::env::var("API_BASE_URL").expect("Define API_BASE_URL");
+
+    let res = client
+        .post(format!("{}/api/users/password-reset/confirm", api_base_url))
+        .json(&payload)
+        .headers(headers.unwrap_or_default())
+        .headers(get_trace_info())
+        .send()
+        .await?;
+    match res.status() {
+        status if status.is_success() => Ok(res
+            .json::<DataBody<model::UserTransportModel>>()
+            .await?
+            .data),
+        reqwest::StatusCode::BAD_REQUEST => Err(Error::BadRequest),
+        reqwest::StatusCode::UNAUTHORIZED => Err(Error::Unauthorized),
+        reqwest::StatusCode::TOO_MANY_REQUESTS => Err(too_many_requests(&res)),
+        status => {
+            tracing::error!("api-server failed to reset the password: {}", status);
+            Err(Error::InternalServerError)
+        }
+    }
+}
+
 #[tracing::instrument]
 pub async fn get_user(
     id: uuid::Uuid,
@@ -126,6 +230,7 @@ pub async fn get_user(
     let req = client
         .get(format!("{}/api/users/{}", api_base_url, id))
         .headers(headers.unwrap_or_default())
+        .headers(service_auth_headers())
         .send();
     let res = req.await.unwrap();
     match res.json::<DataBody<model::UserTransportModel>>().await {
@@ -140,7 +245,84 @@ pub async fn get_user(
     }
 }
 
+/// Authenticates as a service, with the key in `API_SERVICE_KEY` (one of the
+/// api-server's `API_KEYS`), for api-server routes that need credentials.
+fn service_auth_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    match std::env::var("API_SERVICE_KEY") {
+        Ok(key) => {
+            let value = HeaderValue::try_from(format!("Bearer {}", key))
+                .expect("API_SERVICE_KEY must be a valid header value");
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
+        Err(_) => tracing::warn!("API_SERVICE_KEY is not set, the api-server will reject us"),
+    }
+    headers
+}
+
+/// Logs in with an identity verified by an OpenID Connect provider, creating
+/// or linking the user on first login.
+#[tracing::instrument]
+pub async fn link_identity(
+    payload: schema::LinkIdentity,
+    headers: Option<HeaderMap>,
+) -> Result<model::UserTransportModel, Error> {
+    let client = get_client();
+    let api_base_url = std::env::var("API_BASE_URL").expect("Define API_BASE_URL");
+
+    let res = client
+        .post(format!("{}/api/identities", api_base_url))
+        .json(&payload)
+        .headers(headers.unwrap_or_default())
+        .headers(service_auth_headers())
+        .headers(get_trace_info())
+        .send()
+        .await?;
+    match res.status() {
+        status if status.is_success() => Ok(res
+            .json::<DataBody<model::UserTransportModel>>()
+            .await?
+            .data),
+        reqwest::StatusCode::CONFLICT => Err(Error::Conflict(
+            "identity is linked to another user".to_string(),
+        )),
+        status => {
+            tracing::error!("api-server failed to link the identity: {}", status);
+            Err(Error::InternalServerError)
+        }
+    }
+}
+
+/// The user's roles and permissions, see `shared::permission`.
 #[tracing::instrument]
+pub async fn get_user_permissions(
+    id: uuid::Uuid,
+    headers: Option<HeaderMap>,
+) -> Result<model::UserPermissionsModel, Error> {
+    let client = get_client();
+    let api_base_url = std::env::var("API_BASE_URL").expect("Define API_BASE_URL");
+
+    let res = client
+        .get(format!("{}/api/users/{}/permissions", api_base_url, id))
+        .headers(headers.unwrap_or_default())
+        .headers(service_auth_headers())
+        .headers(get_trace_info())
+        .send()
+        .await?;
+    match res.status() {
+        status if status.is_success() => Ok(res
+            .json::<DataBody<model::UserPermissionsModel>>()
+            .await?
+            .data),
+        reqwest::StatusCode::NOT_FOUND => Err(Error::NotFound),
+        status => {
+            tracing::error!("api-server failed to get permissions: {}", status);
+            Err(Error::InternalServerError)
+        }
+    }
+}
+
+#[tracing::instrument(skip(token))]
 pub async fn get_client_by_token(
     token: String,
     headers: Option<HeaderMap>,
@@ -159,6 +341,10 @@ pub async fn get_client_by_token(
     tracing::info!("request being sent: {:?}", req);
     let res = req.send().await?;
     tracing::info!("response body: {:?}", res);
+    if res.status().is_server_error() {
+        tracing::error!("api-server failed to validate the token: {}", res.status());
+        return Err(Error::InternalServerError);
+    }
     match res.json::<DataBody<model::ClientModel>>().await {
         Ok(client) => {
             tracing::debug!("user id: {:?}", client.data.user_id);
@@ -0,0 +1,214 @@
+//! The address a request came from, for per-IP rate limits.
+//!
+//! Servers must be run with
+//! `into_make_service_with_connect_info::<SocketAddr>()`. Behind a proxy,
+//! such as the website in front of the api-server or a load balancer in
+//! front of the website, list the proxy's addresses or networks in
+//! `TRUSTED_PROXIES`, e.g. `10.0.0.7,172.16.0.0/12`. For requests from
+//! those, the address the proxy appended to `X-Forwarded-For` is used
+//! instead of the proxy's own. Anyone else could pick their address with
+//! the header, so it is ignored for them.
+use crate::error::Error;
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap, HeaderValue},
+};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+
+pub const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl ClientIp {
+    /// Passes the address on to the api-server, which needs us in its
+    /// `TRUSTED_PROXIES` to use it.
+    pub fn to_headers(self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = HeaderValue::from_str(&self.0.to_string()).expect("IP is a valid header");
+        headers.insert(X_FORWARDED_FOR, value);
+        headers
+    }
+}
+
+/// An address or network in `TRUSTED_PROXIES`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Network {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    /// `10.0.0.7` or `10.0.0.0/8`, likewise for IPv6.
+    fn parse(network: &str) -> Option<Self> {
+        let (addr, prefix) = match network.split_once('/') {
+            Some((addr, prefix)) => (addr.parse().ok()?, Some(prefix.parse().ok()?)),
+            None => (network.parse().ok()?, None),
+        };
+        let bits = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = prefix.unwrap_or(bits);
+        (prefix <= bits).then_some(Self { addr, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => same_prefix(
+                u32::from(net).into(),
+                u32::from(ip).into(),
+                32 - self.prefix,
+            ),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                same_prefix(net.into(), ip.into(), 128 - self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether `a` and `b` only differ in the lowest `host_bits` bits.
+fn same_prefix(a: u128, b: u128, host_bits: u32) -> bool {
+    // shifting out all 128 bits leaves nothing to compare
+    a.checked_shr(host_bits).unwrap_or(0) == b.checked_shr(host_bits).unwrap_or(0)
+}
+
+/// IPv4 peers of dual-stack listeners show up as `::ffff:a.b.c.d`.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+fn trusted_proxies() -> &'static [Network] {
+    static TRUSTED: OnceLock<Vec<Network>> = OnceLock::new();
+    TRUSTED.get_or_init(|| {
+        let networks = std::env::var("TRUSTED_PROXIES").unwrap_or_default();
+        networks
+            .split(',')
+            .map(str::trim)
+            .filter(|network| !network.is_empty())
+            .map(|network| {
+                Network::parse(network).unwrap_or_else(|| {
+                    panic!(
+                        "TRUSTED_PROXIES entry {} is not an address or network",
+                        network
+                    )
+                })
+            })
+            .collect()
+    })
+}
+
+/// The forwarded address for requests from a `trusted` proxy, otherwise
+/// the peer's own.
+fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[Network]) -> IpAddr {
+    if trusted.iter().any(|network| network.contains(peer)) {
+        if let Some(ip) = forwarded_for(headers) {
+            return ip;
+        }
+    }
+    peer
+}
+
+/// The last address in `X-Forwarded-For`, the one added by the proxy in
+/// front of us. Earlier ones are up to the client.
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    let header = headers.get_all(X_FORWARDED_FOR).iter().next_back()?;
+    header
+        .to_str()
+        .ok()?
+        .rsplit(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(addr)) => Ok(ClientIp(client_ip(
+                addr.ip(),
+                &parts.headers,
+                trusted_proxies(),
+            ))),
+            None => {
+                tracing::error!(
+                    "No client address, serve with into_make_service_with_connect_info"
+                );
+                Err(Error::InternalServerError)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client_ip::*;
+
+    #[test]
+    fn test_forwarded_for() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(forwarded_for(&headers), None);
+
+        headers.append(X_FORWARDED_FOR, "203.0.113.7".parse().unwrap());
+        assert_eq!(forwarded_for(&headers), "203.0.113.7".parse().ok());
+
+        // the client may send its own header, the proxy appends to it
+        headers.append(X_FORWARDED_FOR, "10.0.0.1, 2001:db8::1".parse().unwrap());
+        assert_eq!(forwarded_for(&headers), "2001:db8::1".parse().ok());
+
+        headers.insert(X_FORWARDED_FOR, "unknown".parse().unwrap());
+        assert_eq!(forwarded_for(&headers), None);
+    }
+
+    #[test]
+    fn test_networks() {
+        let network = |s: &str| Network::parse(s).unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        assert!(network("10.0.0.7").contains(ip("10.0.0.7")));
+        assert!(!network("10.0.0.7").contains(ip("10.0.0.8")));
+        assert!(network("172.16.0.0/12").contains(ip("172.31.255.1")));
+        assert!(!network("172.16.0.0/12").contains(ip("172.32.0.1")));
+        assert!(network("127.0.0.1").contains(ip("::ffff:127.0.0.1")));
+        assert!(network("2001:db8::/32").contains(ip("2001:db8::1")));
+        assert!(!network("2001:db8::/32").contains(ip("10.0.0.7")));
+        assert!(network("0.0.0.0/0").contains(ip("203.0.113.7")));
+        assert!(network("::/0").contains(ip("2001:db8::1")));
+        assert_eq!(Network::parse("10.0.0.0/33"), None);
+        assert_eq!(Network::parse("proxy"), None);
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let trusted = [Network::parse("10.0.0.0/8").unwrap()];
+        let proxy = "10.0.0.7".parse().unwrap();
+        let stranger = "198.51.100.1".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        assert_eq!(client_ip(proxy, &headers, &trusted), proxy);
+
+        headers.insert(X_FORWARDED_FOR, "203.0.113.7".parse().unwrap());
+        assert_eq!(
+            client_ip(proxy, &headers, &trusted),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+        // anyone else could pick a fresh address for every request
+        assert_eq!(client_ip(stranger, &headers, &trusted), stranger);
+        assert_eq!(client_ip(proxy, &headers, &[]), proxy);
+    }
+
+    #[test]
+    fn test_to_headers() {
+        let ip = ClientIp("203.0.113.7".parse().unwrap());
+        assert_eq!(forwarded_for(&ip.to_headers()), Some(ip.0));
+    }
+}
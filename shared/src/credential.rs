@@ -0,0 +1,159 @@
+//! Bearer credential validation for `auth::auth`. Each router picks the
+//! validator that fits its callers:
+//!
+//! ```ignore
+//! .route_layer(middleware::from_fn_with_state(
+//!     Arc::new(StaticApiKeyValidator::from_env()) as Arc<dyn CredentialValidator>,
+//!     auth::auth,
+//! ))
+//! ```
+//!
+//! Validators must never log the credential they are given.
+use crate::{authz::Principal, error::Error, jwt::Claims, model::ClientModel};
+use axum::async_trait;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// What a bearer credential proved about the caller. `auth::auth` inserts it
+/// into the request extensions.
+#[derive(Debug, Clone)]
+pub enum Credential {
+    /// One of the configured static API keys, identified by its name.
+    ApiKey {
+        name: String,
+    },
+    Client(ClientModel),
+    Jwt(Claims),
+}
+
+impl Credential {
+    /// The OpenFGA user acting through this credential, if any.
+    pub fn principal(&self) -> Option<Principal> {
+        match self {
+            Credential::ApiKey { .. } => None,
+            Credential::Client(client) => Some(Principal::user(client.user_id)),
+            Credential::Jwt(claims) => Some(Principal(format!("user:{}", claims.sub))),
+        }
+    }
+}
+
+#[async_trait]
+pub trait CredentialValidator: std::fmt::Debug + Send + Sync {
+    async fn validate(&self, credential: &str) -> Result<Credential, Error>;
+}
+
+/// Fixed API keys, e.g. for other services or monitoring.
+#[derive(Debug, Default)]
+pub struct StaticApiKeyValidator {
+    // names and SHA-256 digests of the keys, so lookups don't leak timing
+    // information about the keys themselves
+    keys: Vec<(String, [u8; 32])>,
+}
+
+impl StaticApiKeyValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_key(mut self, name: impl Into<String>, key: &str) -> Self {
+        self.keys.push((name.into(), digest(key)));
+        self
+    }
+
+    /// Reads `API_KEYS`, a comma separated list of `name:key` pairs.
+    pub fn from_env() -> Self {
+        let keys = std::env::var("API_KEYS").unwrap_or_default();
+        keys.split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .fold(Self::new(), |validator, entry| {
+                let (name, key) = entry
+                    .trim()
+                    .split_once(':')
+                    .expect("API_KEYS entries must look like name:key");
+                validator.with_key(name, key)
+            })
+    }
+}
+
+#[async_trait]
+impl CredentialValidator for StaticApiKeyValidator {
+    async fn validate(&self, credential: &str) -> Result<Credential, Error> {
+        let candidate = digest(credential);
+        self.keys
+            .iter()
+            .find(|(_, key)| *key == candidate)
+            .map(|(name, _)| Credential::ApiKey { name: name.clone() })
+            .ok_or(Error::Unauthorized)
+    }
+}
+
+/// Accepts a credential if any of its validators does, trying them in order,
+/// e.g. API keys for services and JWTs for users on the same routes.
+#[derive(Debug, Default)]
+pub struct AnyValidator {
+    validators: Vec<Arc<dyn CredentialValidator>>,
+}
+
+impl AnyValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, validator: impl CredentialValidator + 'static) -> Self {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+}
+
+#[async_trait]
+impl CredentialValidator for AnyValidator {
+    async fn validate(&self, credential: &str) -> Result<Credential, Error> {
+        // keeps a reason like an expired token to tell the caller
+        let mut rejection = Error::Unauthorized;
+        for validator in &self.validators {
+            match validator.validate(credential).await {
+                Ok(credential) => return Ok(credential),
+                Err(e @ Error::Auth(_)) => rejection = e,
+                Err(_) => {}
+            }
+        }
+        Err(rejection)
+    }
+}
+
+fn digest(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::credential::*;
+
+    #[tokio::test]
+    async fn test_static_api_keys() {
+        let validator = StaticApiKeyValidator::new()
+            .with_key("monitoring", "key-1")
+            .with_key("billing", "key-2");
+
+        let res = validator.validate("key-2").await;
+        assert!(matches!(res, Ok(Credential::ApiKey { name }) if name == "billing"));
+        let res = validator.validate("key-3").await;
+        assert!(matches!(res, Err(Error::Unauthorized)));
+        let res = StaticApiKeyValidator::new().validate("").await;
+        assert!(matches!(res, Err(Error::Unauthorized)));
+    }
+
+    #[tokio::test]
+    async fn test_any_validator() {
+        let validator = AnyValidator::new()
+            .with(StaticApiKeyValidator::new().with_key("monitoring", "key-1"))
+            .with(StaticApiKeyValidator::new().with_key("billing", "key-2"));
+
+        let res = validator.validate("key-2").await;
+        assert!(matches!(res, Ok(Credential::ApiKey { name }) if name == "billing"));
+        let res = validator.validate("key-3").await;
+        assert!(matches!(res, Err(Error::Unauthorized)));
+        let res = AnyValidator::new().validate("key-1").await;
+        assert!(matches!(res, Err(Error::Unauthorized)));
+    }
+}
@@ -1,26 +1,91 @@
 use anyhow;
 use axum;
 use axum::extract::rejection::JsonRejection;
-use axum::http::StatusCode;
+use axum::http::{header::RETRY_AFTER, StatusCode};
 use axum::response::{IntoResponse, Json, Response};
+use jsonwebtoken::errors::ErrorKind;
 use reqwest;
 use reqwest_middleware;
 use serde::{Deserialize, Serialize};
 
+/// Why a bearer or ID token was rejected.
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum AuthError {
+    #[error("Token is malformed: {0}")]
+    Malformed(String),
+
+    #[error("Token has no key id")]
+    MissingKeyId,
+
+    #[error("Token is signed with an unknown key '{0}'")]
+    UnknownKey(String),
+
+    #[error("Token is signed with a disallowed algorithm")]
+    DisallowedAlgorithm,
+
+    #[error("Token signature is invalid")]
+    InvalidSignature,
+
+    #[error("Token has expired")]
+    Expired,
+
+    #[error("Token is not valid yet")]
+    NotYetValid,
+
+    #[error("Token has the wrong audience")]
+    InvalidAudience,
+
+    #[error("Token has the wrong issuer")]
+    InvalidIssuer,
+
+    #[error("Token lacks the '{0}' claim")]
+    MissingClaim(String),
+
+    #[error("Token signing keys are unavailable")]
+    KeysUnavailable,
+}
+
+impl From<jsonwebtoken::errors::Error> for AuthError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        match e.kind() {
+            ErrorKind::InvalidSignature => AuthError::InvalidSignature,
+            ErrorKind::ExpiredSignature => AuthError::Expired,
+            ErrorKind::ImmatureSignature => AuthError::NotYetValid,
+            ErrorKind::InvalidAudience => AuthError::InvalidAudience,
+            ErrorKind::InvalidIssuer => AuthError::InvalidIssuer,
+            ErrorKind::InvalidAlgorithm => AuthError::DisallowedAlgorithm,
+            ErrorKind::MissingRequiredClaim(claim) => AuthError::MissingClaim(claim.clone()),
+            _ => AuthError::Malformed(e.to_string()),
+        }
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Request lacks valid authentication credentials for the requested resource")]
     Unauthorized,
 
+    #[error(transparent)]
+    Auth(#[from] AuthError),
+
     #[error("Server understands the request but refuses to authorize it")]
     Forbidden,
 
+    #[error("Client token lacks the required scope '{0}'")]
+    MissingScope(String),
+
     #[error("Not found")]
     NotFound,
 
     #[error("Bad request")]
     BadRequest,
 
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Too many requests, retry after {retry_after_seconds} seconds")]
+    TooManyRequests { retry_after_seconds: u64 },
+
     #[error("Internal server error")]
     InternalServerError,
 
@@ -41,15 +106,33 @@ pub enum Error {
 
     #[error("An axum error occurred")]
     SerdeJson(#[from] serde_json::Error),
+
+    #[error("OpenFGA request failed with status {status}: {code} {message}")]
+    OpenFga {
+        status: u16,
+        code: String,
+        message: String,
+    },
 }
 
 impl Error {
     fn code_detail(&self) -> (StatusCode, String) {
         let code = match self {
             Error::Unauthorized => StatusCode::UNAUTHORIZED,
+            Error::Auth(AuthError::KeysUnavailable) => {
+                tracing::error!("Auth error: {}", self);
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Service unavailable".to_string(),
+                );
+            }
+            Error::Auth(_) => StatusCode::UNAUTHORIZED,
             Error::Forbidden => StatusCode::FORBIDDEN,
+            Error::MissingScope(_) => StatusCode::FORBIDDEN,
             Error::NotFound => StatusCode::NOT_FOUND,
             Error::BadRequest => StatusCode::BAD_REQUEST,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
             Error::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
             Error::Anyhow(e) => {
                 tracing::error!("Anyhow error: {:?}", e);
@@ -79,6 +162,17 @@ impl Error {
                     "Internal server error".to_string(),
                 );
             }
+            Error::OpenFga { status, .. } => {
+                tracing::error!("OpenFGA error: {}", self);
+                return match *status {
+                    400 => (StatusCode::BAD_REQUEST, "Bad request".to_string()),
+                    404 => (StatusCode::NOT_FOUND, "Not found".to_string()),
+                    _ => (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Internal server error".to_string(),
+                    ),
+                };
+            }
             Error::Sqlx(e) => match e {
                 sqlx::Error::RowNotFound => {
                     return (
@@ -128,6 +222,12 @@ struct ErrorJson {
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
+        let retry_after = match self {
+            Error::TooManyRequests {
+                retry_after_seconds,
+            } => Some(retry_after_seconds),
+            _ => None,
+        };
         let (code, detail) = self.code_detail();
 
         let res: Vec<ErrorJson> = [ErrorJson {
@@ -138,7 +238,12 @@ impl IntoResponse for Error {
         }]
         .to_vec();
 
-        (code, Json(res)).into_response()
+        match retry_after {
+            Some(seconds) => {
+                (code, [(RETRY_AFTER, seconds.to_string())], Json(res)).into_response()
+            }
+            None => (code, Json(res)).into_response(),
+        }
     }
 }
 
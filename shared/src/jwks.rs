@@ -0,0 +1,430 @@
+use crate::{
+    credential::{Credential, CredentialValidator},
+    error::{AuthError, Error},
+    jwt::Claims,
+};
+use axum::async_trait;
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{JwkSet, PublicKeyUse},
+    Algorithm, DecodingKey, Validation,
+};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, Extension};
+use reqwest_tracing::{OtelName, TracingMiddleware};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(600);
+// Unknown `kid`s trigger a refetch (the issuer may have rotated its keys),
+// but no more often than this so forged tokens can't hammer the issuer.
+const DEFAULT_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_LEEWAY_SECONDS: u64 = 60;
+
+fn get_client() -> ClientWithMiddleware {
+    ClientBuilder::new(reqwest::Client::new())
+        .with_init(Extension(OtelName("jwks-client".into())))
+        .with(TracingMiddleware::default())
+        .build()
+}
+
+#[derive(Clone)]
+struct FetchedKeys {
+    keys: HashMap<String, DecodingKey>,
+    fetched_at: Instant,
+}
+
+/// Validates JWTs signed by an issuer that publishes its keys as a JWKS
+/// document, e.g. an OIDC provider.
+///
+/// Keys are picked by the token's `kid` and cached; `iss`, `aud`, `exp` and
+/// `nbf` are checked with [`JwksValidator::with_leeway`] of clock skew.
+pub struct JwksValidator {
+    http_client: ClientWithMiddleware,
+    jwks_url: String,
+    validation: Validation,
+    cache_ttl: Duration,
+    min_refresh_interval: Duration,
+    keys: RwLock<Option<FetchedKeys>>,
+}
+
+impl std::fmt::Debug for JwksValidator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwksValidator")
+            .field("jwks_url", &self.jwks_url)
+            .field("validation", &self.validation)
+            .finish_non_exhaustive()
+    }
+}
+
+impl JwksValidator {
+    pub fn new(jwks_url: impl Into<String>, issuer: &str, audience: &str) -> Self {
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_issuer(&[issuer]);
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.validate_nbf = true;
+        validation.leeway = DEFAULT_LEEWAY_SECONDS;
+        Self {
+            http_client: get_client(),
+            jwks_url: jwks_url.into(),
+            validation,
+            cache_ttl: DEFAULT_CACHE_TTL,
+            min_refresh_interval: DEFAULT_MIN_REFRESH_INTERVAL,
+            keys: RwLock::new(None),
+        }
+    }
+
+    /// Allowed signing algorithms, `RS256` by default.
+    pub fn with_algorithms(mut self, algorithms: &[Algorithm]) -> Self {
+        self.validation.algorithms = algorithms.to_vec();
+        self
+    }
+
+    /// Clock skew tolerated for `exp` and `nbf`, 60 seconds by default.
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.validation.leeway = leeway.as_secs();
+        self
+    }
+
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    pub fn with_min_refresh_interval(mut self, interval: Duration) -> Self {
+        self.min_refresh_interval = interval;
+        self
+    }
+
+    /// Reads `JWT_JWKS_URL`, `JWT_ISSUER`, `JWT_AUDIENCE` and the optional
+    /// `JWT_LEEWAY_SECONDS`.
+    pub fn from_env() -> Self {
+        let jwks_url = std::env::var("JWT_JWKS_URL").expect("JWT_JWKS_URL must be set");
+        let issuer = std::env::var("JWT_ISSUER").expect("JWT_ISSUER must be set");
+        let audience = std::env::var("JWT_AUDIENCE").expect("JWT_AUDIENCE must be set");
+        let validator = Self::new(jwks_url, &issuer, &audience);
+        match std::env::var("JWT_LEEWAY_SECONDS") {
+            Ok(leeway) => validator.with_leeway(Duration::from_secs(
+                leeway.parse().expect("JWT_LEEWAY_SECONDS must be a number"),
+            )),
+            Err(_) => validator,
+        }
+    }
+
+    /// Decodes `token` and checks its signature and registered claims.
+    pub async fn validate_token(&self, token: &str) -> Result<Claims, AuthError> {
+        self.decode(token).await.map_err(|e| {
+            tracing::info!("JWT rejected: {}", e);
+            e
+        })
+    }
+
+    async fn decode(&self, token: &str) -> Result<Claims, AuthError> {
+        let header = decode_header(token)?;
+        if !self.validation.algorithms.contains(&header.alg) {
+            return Err(AuthError::DisallowedAlgorithm);
+        }
+        let kid = header.kid.ok_or(AuthError::MissingKeyId)?;
+        let key = self.key(&kid).await?;
+
+        // jsonwebtoken wants every allowed algorithm to match the key's family
+        let mut validation = self.validation.clone();
+        validation.algorithms = vec![header.alg];
+        Ok(decode::<Claims>(token, &key, &validation)?.claims)
+    }
+
+    async fn key(&self, kid: &str) -> Result<DecodingKey, AuthError> {
+        if let Some(cached) = self.keys.read().await.as_ref() {
+            let age = cached.fetched_at.elapsed();
+            if age < self.cache_ttl {
+                if let Some(key) = cached.keys.get(kid) {
+                    return Ok(key.clone());
+                }
+            }
+            if age < self.min_refresh_interval {
+                return Err(AuthError::UnknownKey(kid.to_string()));
+            }
+        }
+
+        let mut cached = self.keys.write().await;
+        // another request may have refreshed the keys while we waited
+        let refreshed = cached
+            .as_ref()
+            .filter(|cached| cached.fetched_at.elapsed() < self.min_refresh_interval);
+        let keys = match refreshed {
+            Some(keys) => keys.clone(),
+            None => match self.fetch().await {
+                Ok(keys) => {
+                    *cached = Some(keys.clone());
+                    keys
+                }
+                Err(e) => {
+                    tracing::error!("Failed to fetch JWKS from {}: {}", self.jwks_url, e);
+                    // keep using the keys we have until the issuer is back
+                    cached.clone().ok_or(AuthError::KeysUnavailable)?
+                }
+            },
+        };
+        keys.keys
+            .get(kid)
+            .cloned()
+            .ok_or_else(|| AuthError::UnknownKey(kid.to_string()))
+    }
+
+    #[tracing::instrument(skip(self), fields(jwks_url = %self.jwks_url))]
+    async fn fetch(&self) -> Result<FetchedKeys, Error> {
+        let jwks: JwkSet = self
+            .http_client
+            .get(&self.jwks_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let keys = jwks
+            .keys
+            .iter()
+            .filter(|jwk| {
+                !matches!(
+                    jwk.common.public_key_use,
+                    Some(PublicKeyUse::Encryption) | Some(PublicKeyUse::Other(_))
+                )
+            })
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                match DecodingKey::from_jwk(jwk) {
+                    Ok(key) => Some((kid, key)),
+                    Err(e) => {
+                        tracing::warn!("Skipping JWK {}: {}", kid, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        Ok(FetchedKeys {
+            keys,
+            fetched_at: Instant::now(),
+        })
+    }
+}
+
+#[async_trait]
+impl CredentialValidator for JwksValidator {
+    async fn validate(&self, credential: &str) -> Result<Credential, Error> {
+        Ok(Credential::Jwt(self.validate_token(credential).await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jwks::*;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+    use openssl::{
+        bn::{BigNum, BigNumContext},
+        ec::{EcGroup, EcKey},
+        nid::Nid,
+        pkey::PKey,
+        rsa::Rsa,
+    };
+    use serde_json::{json, Value};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const ISSUER: &str = "https://issuer.example/";
+    const AUDIENCE: &str = "api";
+
+    fn base64url(bytes: &[u8]) -> String {
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    struct TestKey {
+        kid: String,
+        encoding: EncodingKey,
+        jwk: Value,
+    }
+
+    fn rsa_key(kid: &str) -> TestKey {
+        let rsa = Rsa::generate(2048).unwrap();
+        TestKey {
+            kid: kid.to_string(),
+            encoding: EncodingKey::from_rsa_pem(&rsa.private_key_to_pem().unwrap()).unwrap(),
+            jwk: json!({
+                "kty": "RSA",
+                "use": "sig",
+                "alg": "RS256",
+                "kid": kid,
+                "n": base64url(&rsa.n().to_vec()),
+                "e": base64url(&rsa.e().to_vec()),
+            }),
+        }
+    }
+
+    fn ec_key(kid: &str) -> TestKey {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let (mut x, mut y) = (BigNum::new().unwrap(), BigNum::new().unwrap());
+        key.public_key()
+            .affine_coordinates(&group, &mut x, &mut y, &mut BigNumContext::new().unwrap())
+            .unwrap();
+        let pem = PKey::from_ec_key(key)
+            .unwrap()
+            .private_key_to_pem_pkcs8()
+            .unwrap();
+        TestKey {
+            kid: kid.to_string(),
+            encoding: EncodingKey::from_ec_pem(&pem).unwrap(),
+            jwk: json!({
+                "kty": "EC",
+                "crv": "P-256",
+                "kid": kid,
+                "x": base64url(&x.to_vec_padded(32).unwrap()),
+                "y": base64url(&y.to_vec_padded(32).unwrap()),
+            }),
+        }
+    }
+
+    fn sign(key: &TestKey, alg: Algorithm, claims: &Value) -> String {
+        let mut header = Header::new(alg);
+        header.kid = Some(key.kid.clone());
+        encode(&header, claims, &key.encoding).unwrap()
+    }
+
+    fn id_token_claims() -> Value {
+        json!({
+            "sub": "36f9424f-f929-4c78-a28f-6f6c9fcc93b4",
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "exp": get_current_timestamp() + 3600,
+            "email": "user@example.com",
+        })
+    }
+
+    async fn jwks_server(keys: &[&TestKey]) -> MockServer {
+        let server = MockServer::start().await;
+        let jwks = json!({ "keys": keys.iter().map(|k| k.jwk.clone()).collect::<Vec<_>>() });
+        Mock::given(method("GET"))
+            .and(path("/.well-known/jwks.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(jwks))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn validator(server: &MockServer) -> JwksValidator {
+        JwksValidator::new(
+            format!("{}/.well-known/jwks.json", server.uri()),
+            ISSUER,
+            AUDIENCE,
+        )
+        .with_algorithms(&[Algorithm::RS256, Algorithm::ES256])
+        .with_leeway(Duration::from_secs(30))
+    }
+
+    #[tokio::test]
+    async fn test_selects_key_by_kid() {
+        let (rsa, ec) = (rsa_key("rsa-1"), ec_key("ec-1"));
+        let server = jwks_server(&[&rsa, &ec]).await;
+        let validator = validator(&server);
+
+        let claims = validator
+            .validate_token(&sign(&rsa, Algorithm::RS256, &id_token_claims()))
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "36f9424f-f929-4c78-a28f-6f6c9fcc93b4");
+        assert_eq!(claims.extra["email"], "user@example.com");
+        let res = validator
+            .validate_token(&sign(&ec, Algorithm::ES256, &id_token_claims()))
+            .await;
+        assert!(res.is_ok());
+
+        // signed with a key that isn't published under that kid
+        let forged = TestKey {
+            kid: "rsa-1".to_string(),
+            ..rsa_key("other")
+        };
+        let res = validator
+            .validate_token(&sign(&forged, Algorithm::RS256, &id_token_claims()))
+            .await;
+        assert_eq!(res.unwrap_err(), AuthError::InvalidSignature);
+        let res = validator
+            .validate_token(&sign(
+                &rsa_key("rsa-2"),
+                Algorithm::RS256,
+                &id_token_claims(),
+            ))
+            .await;
+        assert_eq!(res.unwrap_err(), AuthError::UnknownKey("rsa-2".to_string()));
+        // not in the allowed algorithms
+        let res = validator
+            .validate_token(&sign(&rsa, Algorithm::RS512, &id_token_claims()))
+            .await;
+        assert_eq!(res.unwrap_err(), AuthError::DisallowedAlgorithm);
+        let res = validator.validate_token("not.a.jwt").await;
+        assert!(matches!(res, Err(AuthError::Malformed(_))));
+
+        // keys are cached
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_refetches_rotated_keys() {
+        let (old, new) = (rsa_key("old"), rsa_key("new"));
+        let server = jwks_server(&[&old]).await;
+        let validator = validator(&server).with_min_refresh_interval(Duration::ZERO);
+        let res = validator
+            .validate_token(&sign(&old, Algorithm::RS256, &id_token_claims()))
+            .await;
+        assert!(res.is_ok());
+
+        server.reset().await;
+        let jwks = json!({ "keys": [old.jwk, new.jwk] });
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(jwks))
+            .mount(&server)
+            .await;
+        let res = validator
+            .validate_token(&sign(&new, Algorithm::RS256, &id_token_claims()))
+            .await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_registered_claims() {
+        let key = rsa_key("rsa-1");
+        let server = jwks_server(&[&key]).await;
+        let validator = validator(&server);
+        let now = get_current_timestamp();
+        let check = |field: &str, value: Value| {
+            let mut claims = id_token_claims();
+            claims[field] = value;
+            sign(&key, Algorithm::RS256, &claims)
+        };
+
+        for (field, value, expected) in [
+            (
+                "iss",
+                json!("https://other.example/"),
+                Err(AuthError::InvalidIssuer),
+            ),
+            ("aud", json!("website"), Err(AuthError::InvalidAudience)),
+            ("aud", json!(["website", AUDIENCE]), Ok(())),
+            ("exp", json!(now - 120), Err(AuthError::Expired)),
+            // within the leeway
+            ("exp", json!(now - 10), Ok(())),
+            ("nbf", json!(now + 120), Err(AuthError::NotYetValid)),
+            ("nbf", json!(now + 10), Ok(())),
+        ] {
+            let res = validator.validate_token(&check(field, value.clone())).await;
+            assert_eq!(res.map(|_| ()), expected, "{} = {}", field, value);
+        }
+
+        let mut claims = id_token_claims();
+        claims.as_object_mut().unwrap().remove("aud");
+        let res = validator
+            .validate_token(&sign(&key, Algorithm::RS256, &claims))
+            .await;
+        assert_eq!(res.unwrap_err(), AuthError::MissingClaim("aud".to_string()));
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{
+    credential::{Credential, CredentialValidator},
+    error::{AuthError, Error},
+};
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Registered claims we rely on; everything else is kept in `extra`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The claims of the JWT the request was authenticated with by `auth::auth`,
+/// with a JWT validator such as [`JwtValidator`] or `jwks::JwksValidator`.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Claims {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<Credential>() {
+            Some(Credential::Jwt(claims)) => Ok(claims.clone()),
+            _ => Err(Error::Unauthorized),
+        }
+    }
+}
+
+/// Validates JWTs signed with a shared HS256 secret.
+pub struct JwtValidator {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl std::fmt::Debug for JwtValidator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtValidator")
+            .field("validation", &self.validation)
+            .finish_non_exhaustive()
+    }
+}
+
+impl JwtValidator {
+    pub fn hs256(secret: &Secret<String>) -> Self {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.validate_aud = false;
+        Self {
+            key: DecodingKey::from_secret(secret.expose_secret().as_bytes()),
+            validation,
+        }
+    }
+
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.validation.set_issuer(&[issuer]);
+        self
+    }
+
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.validation.set_audience(&[audience]);
+        self.validation.validate_aud = true;
+        self
+    }
+
+    /// Reads `JWT_HS256_SECRET` and the optional `JWT_ISSUER` and `JWT_AUDIENCE`.
+    pub fn from_env() -> Self {
+        let secret = std::env::var("JWT_HS256_SECRET").expect("JWT_HS256_SECRET must be set");
+        let mut validator = Self::hs256(&Secret::new(secret));
+        if let Ok(issuer) = std::env::var("JWT_ISSUER") {
+            validator = validator.with_issuer(&issuer);
+        }
+        if let Ok(audience) = std::env::var("JWT_AUDIENCE") {
+            validator = validator.with_audience(&audience);
+        }
+        validator
+    }
+}
+
+#[async_trait]
+impl CredentialValidator for JwtValidator {
+    async fn validate(&self, credential: &str) -> Result<Credential, Error> {
+        let token = decode::<Claims>(credential, &self.key, &self.validation).map_err(|e| {
+            let e = AuthError::from(e);
+            tracing::info!("JWT rejected: {}", e);
+            e
+        })?;
+        Ok(Credential::Jwt(token.claims))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jwt::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    fn token(secret: &str, claims: Value) -> String {
+        encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    fn in_an_hour() -> u64 {
+        jsonwebtoken::get_current_timestamp() + 3600
+    }
+
+    #[tokio::test]
+    async fn test_hs256() {
+        let validator = JwtValidator::hs256(&Secret::new("secret".to_string()))
+            .with_issuer("https://issuer.example")
+            .with_audience("api");
+        let claims = json!({
+            "sub": "36f9424f-f929-4c78-a28f-6f6c9fcc93b4",
+            "exp": in_an_hour(),
+            "iss": "https://issuer.example",
+            "aud": "api",
+            "scope": "accounts:read"
+        });
+
+        let res = validator.validate(&token("secret", claims.clone())).await;
+        let Ok(Credential::Jwt(validated)) = res else {
+            panic!("expected a valid JWT, got {:?}", res);
+        };
+        assert_eq!(validated.sub, "36f9424f-f929-4c78-a28f-6f6c9fcc93b4");
+        assert_eq!(validated.extra["scope"], "accounts:read");
+
+        let res = validator.validate(&token("other", claims.clone())).await;
+        assert!(matches!(res, Err(Error::Auth(AuthError::InvalidSignature))));
+
+        let mut wrong_audience = claims.clone();
+        wrong_audience["aud"] = json!("website");
+        let res = validator.validate(&token("secret", wrong_audience)).await;
+        assert!(matches!(res, Err(Error::Auth(AuthError::InvalidAudience))));
+
+        let mut expired = claims;
+        expired["exp"] = json!(jsonwebtoken::get_current_timestamp() - 3600);
+        let res = validator.validate(&token("secret", expired)).await;
+        assert!(matches!(res, Err(Error::Auth(AuthError::Expired))));
+    }
+}
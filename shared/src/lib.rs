@@ -1,11 +1,22 @@
 pub mod auth;
+pub mod authz;
 pub mod client;
+pub mod client_ip;
+pub mod credential;
 pub mod error;
+pub mod jwks;
+pub mod jwt;
 pub mod model;
 pub mod openfga;
+pub mod openfga_cache;
+pub mod openfga_memory;
+pub mod openfga_model;
+pub mod permission;
 pub mod schema;
+pub mod scope;
 pub mod startup;
 pub mod telemetry;
+pub mod token_cache;
 pub mod tracing;
 
 pub fn add(left: usize, right: usize) -> usize {
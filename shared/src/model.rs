@@ -1,13 +1,38 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
-#[derive(Debug, FromRow, Deserialize, Serialize)]
+#[derive(Debug, Clone, FromRow, Deserialize, Serialize)]
 #[allow(non_snake_case)]
 pub struct ClientModel {
     pub id: Uuid,
     pub name: String,
     pub user_id: Uuid,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl ClientModel {
+    /// Whether the client's token may be used at `now`.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        let expired = matches!(self.expires_at, Some(expires_at) if expires_at <= now);
+        self.revoked_at.is_none() && !expired
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+}
+
+/// A client together with its token, returned only when the token is
+/// issued. The token can't be recovered afterwards.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreatedClientModel {
+    #[serde(flatten)]
+    pub client: ClientModel,
     pub token: String,
 }
 
@@ -22,7 +47,8 @@ pub struct AccountModel {
 pub struct UserModel {
     pub id: Uuid,
     pub name: String,
-    pub password_hash: String,
+    /// `None` for users who only log in through an identity provider.
+    pub password_hash: Option<String>,
 }
 
 #[derive(Debug, FromRow, Deserialize, Serialize)]
@@ -36,3 +62,11 @@ pub struct UserTransportModel {
     pub id: Uuid,
     pub name: String,
 }
+
+/// A user's roles and the permissions they give, see `permission::for_roles`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UserPermissionsModel {
+    pub user_id: Uuid,
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+}
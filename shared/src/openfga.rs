@@ -1,26 +1,24 @@
 use crate::{
-    error::Error, model, schema, telemetry::init_subscribers_custom,
-    tracing::make_otel_reqwest_span,
+    error::Error,
+    openfga_cache::{CheckCache, CheckCacheKey},
+    openfga_model::AuthorizationModel,
 };
+use axum::async_trait;
 use opentelemetry::{
-    global,
-    global::set_text_map_propagator,
-    propagation::TextMapPropagator,
+    global::set_text_map_propagator, propagation::TextMapPropagator,
     sdk::propagation::TraceContextPropagator,
-    trace::{TraceContextExt, Tracer},
 };
 use reqwest;
-use reqwest::{
-    header::USER_AGENT,
-    header::{HeaderMap, HeaderName, HeaderValue},
-};
-use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, Extension};
-use reqwest_tracing::{OtelName, ReqwestOtelSpanBackend, TracingMiddleware};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, Extension, RequestBuilder};
+use reqwest_tracing::{OtelName, TracingMiddleware};
+use secrecy::{ExposeSecret, Secret};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
-use tracing::{self, info_span, instrument::WithSubscriber, subscriber, Instrument, Span};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tracing;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_opentelemetry_instrumentation_sdk::find_current_trace_id;
 
@@ -40,7 +38,6 @@ fn get_trace_info() -> HeaderMap {
     let propagator = TraceContextPropagator::new();
     let mut fields = HashMap::new();
     propagator.inject_context(&context, &mut fields);
-    propagator.inject_context(&context, &mut fields);
     let headers = fields
         .into_iter()
         .map(|(k, v)| {
@@ -58,38 +55,559 @@ fn get_trace_info() -> HeaderMap {
     headers
 }
 
-#[tracing::instrument]
-pub async fn get_client_by_token(
-    token: String,
-    headers: Option<HeaderMap>,
-) -> Result<model::ClientModel, Error> {
-    let http_client = get_client();
-    let api_base_url = std::env::var("FGA_BASE_URL").expect("Define FGA_BASE_URL");
-
-    let trace_headers = get_trace_info();
-    let body = schema::ValidateToken { token };
-    let req = http_client
-        .get(format!("{}/api/clients/validate_token", api_base_url))
-        .headers(headers.unwrap_or_default())
-        .headers(trace_headers)
-        .json::<schema::ValidateToken>(&body);
-
-    tracing::info!("request being sent: {:?}", req);
-    let res = req.send().await?;
-    tracing::info!("response body: {:?}", res);
-    todo!()
-    /*
-    match res.json::<DataBody<model::ClientModel>>().await {
-        Ok(client) => {
-            tracing::debug!("user id: {:?}", client.data.user_id);
-            Ok(client.data)
+/// Client for a single OpenFGA store and authorization model.
+///
+/// Build it once at startup (usually with [`OpenFgaClient::from_env`]) and
+/// share it between handlers; cloning is cheap.
+#[derive(Debug, Clone)]
+pub struct OpenFgaClient {
+    http_client: ClientWithMiddleware,
+    base_url: String,
+    store_id: Option<String>,
+    authorization_model_id: Option<String>,
+    api_token: Option<Secret<String>>,
+    check_cache: Option<Arc<CheckCache>>,
+}
+
+impl OpenFgaClient {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            http_client: get_client(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            store_id: None,
+            authorization_model_id: None,
+            api_token: None,
+            check_cache: None,
+        }
+    }
+
+    /// Reads `FGA_BASE_URL` and the optional `FGA_STORE_ID`,
+    /// `FGA_AUTHORIZATION_MODEL_ID` and `FGA_API_TOKEN`. See
+    /// [`CheckCache::from_env`] for the check cache settings.
+    pub fn from_env() -> Self {
+        let base_url = std::env::var("FGA_BASE_URL").expect("Define FGA_BASE_URL");
+        let mut client = Self::new(base_url);
+        client.store_id = std::env::var("FGA_STORE_ID").ok();
+        client.authorization_model_id = std::env::var("FGA_AUTHORIZATION_MODEL_ID").ok();
+        client.api_token = std::env::var("FGA_API_TOKEN").ok().map(Secret::new);
+        client.check_cache = CheckCache::from_env().map(Arc::new);
+        client
+    }
+
+    pub fn with_store_id(mut self, store_id: impl Into<String>) -> Self {
+        self.store_id = Some(store_id.into());
+        self
+    }
+
+    pub fn with_authorization_model_id(
+        mut self,
+        authorization_model_id: impl Into<String>,
+    ) -> Self {
+        self.authorization_model_id = Some(authorization_model_id.into());
+        self
+    }
+
+    pub fn with_api_token(mut self, api_token: Secret<String>) -> Self {
+        self.api_token = Some(api_token);
+        self
+    }
+
+    /// Caches check decisions. The cache is shared by all clones of this client.
+    pub fn with_check_cache(mut self, check_cache: CheckCache) -> Self {
+        self.check_cache = Some(Arc::new(check_cache));
+        self
+    }
+
+    pub fn store_id(&self) -> Option<&str> {
+        self.store_id.as_deref()
+    }
+
+    pub fn authorization_model_id(&self) -> Option<&str> {
+        self.authorization_model_id.as_deref()
+    }
+
+    fn configured_store_id(&self) -> Result<String, Error> {
+        self.store_id.clone().ok_or_else(|| {
+            tracing::error!("OpenFGA store id is not configured");
+            Error::InternalServerError
+        })
+    }
+
+    fn store_url(&self, path: &str) -> Result<String, Error> {
+        Ok(format!(
+            "{}/stores/{}/{}",
+            self.base_url,
+            self.configured_store_id()?,
+            path
+        ))
+    }
+
+    /// The cache to consult for a check with `options`, if any.
+    fn check_cache_for(&self, options: &CheckOptions) -> Option<&CheckCache> {
+        self.check_cache
+            .as_deref()
+            .filter(|_| options.is_cacheable())
+    }
+
+    fn cache_key(&self, tuple: &RelationshipTuple) -> Result<CheckCacheKey, Error> {
+        Ok(CheckCacheKey {
+            store_id: self.configured_store_id()?,
+            authorization_model_id: self.model_id()?,
+            tuple: tuple.clone(),
+        })
+    }
+
+    fn model_id(&self) -> Result<String, Error> {
+        self.authorization_model_id.clone().ok_or_else(|| {
+            tracing::error!("OpenFGA authorization model id is not configured");
+            Error::InternalServerError
+        })
+    }
+
+    fn post(&self, url: String) -> RequestBuilder {
+        self.authorize(self.http_client.post(url))
+    }
+
+    fn get(&self, url: String) -> RequestBuilder {
+        self.authorize(self.http_client.get(url))
+    }
+
+    fn authorize(&self, req: RequestBuilder) -> RequestBuilder {
+        let req = req.headers(get_trace_info());
+        match &self.api_token {
+            Some(token) => req.bearer_auth(token.expose_secret()),
+            None => req,
+        }
+    }
+
+    /// Makes sure a store and model exist and returns a client pinned to both.
+    ///
+    /// A configured store id is used as is; otherwise the store called
+    /// `store_name` is looked up and created if missing. Likewise a configured
+    /// model id is kept; otherwise `model` is written unless it is already the
+    /// store's latest model. Safe to run on every startup.
+    #[tracing::instrument(skip(self, model))]
+    pub async fn provision(
+        mut self,
+        store_name: &str,
+        model: &AuthorizationModel,
+    ) -> Result<Self, Error> {
+        if self.store_id.is_none() {
+            let store_id = match self.find_store(store_name).await? {
+                Some(store) => store.id,
+                None => {
+                    tracing::info!("Creating OpenFGA store {}", store_name);
+                    self.create_data_store(CreateDataStoreSchema {
+                        name: store_name.to_string(),
+                    })
+                    .await?
+                    .id
+                }
+            };
+            self.store_id = Some(store_id);
         }
-        Err(e) => {
-            tracing::error!("Error fetching client: {:?}", e);
-            Err(Error::Unauthorized)
+
+        if self.authorization_model_id.is_none() {
+            let model_id = match self.latest_authorization_model().await? {
+                Some(latest) if latest.model.same_as(model) => latest.id,
+                _ => {
+                    tracing::info!("Writing a new OpenFGA authorization model");
+                    self.write_authorization_model(model)
+                        .await?
+                        .authorization_model_id
+                }
+            };
+            self.authorization_model_id = Some(model_id);
         }
+
+        tracing::info!(
+            "Using OpenFGA store {:?} with authorization model {:?}",
+            self.store_id,
+            self.authorization_model_id
+        );
+        Ok(self)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn create_data_store(
+        &self,
+        store: CreateDataStoreSchema,
+    ) -> Result<CreateDataStoreResponse, Error> {
+        let req = self
+            .post(format!("{}/stores", self.base_url))
+            .json::<CreateDataStoreSchema>(&store);
+        tracing::debug!("request being sent: {:?}", req);
+        let res = req.send().await?;
+        tracing::debug!("response body: {:?}", res);
+        handle_response(res).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn list_stores(
+        &self,
+        continuation_token: Option<String>,
+    ) -> Result<ListStoresResponse, Error> {
+        let req = self
+            .get(format!("{}/stores", self.base_url))
+            .query(&[("continuation_token", continuation_token.unwrap_or_default())]);
+        tracing::debug!("request being sent: {:?}", req);
+        let res = req.send().await?;
+        tracing::debug!("response body: {:?}", res);
+        handle_response(res).await
+    }
+
+    /// Finds a store by name. OpenFGA allows duplicate names; the first match wins.
+    #[tracing::instrument(skip(self))]
+    pub async fn find_store(&self, name: &str) -> Result<Option<Store>, Error> {
+        let mut continuation_token = None;
+        loop {
+            let page = self.list_stores(continuation_token).await?;
+            if let Some(store) = page.stores.into_iter().find(|store| store.name == name) {
+                return Ok(Some(store));
+            }
+            if page.continuation_token.is_empty() {
+                return Ok(None);
+            }
+            continuation_token = Some(page.continuation_token);
+        }
+    }
+
+    /// The most recently written model of the configured store, if any.
+    #[tracing::instrument(skip(self))]
+    pub async fn latest_authorization_model(
+        &self,
+    ) -> Result<Option<StoredAuthorizationModel>, Error> {
+        let req = self
+            .get(self.store_url("authorization-models")?)
+            .query(&[("page_size", 1)]);
+        tracing::debug!("request being sent: {:?}", req);
+        let res = req.send().await?;
+        tracing::debug!("response body: {:?}", res);
+        let models: ReadAuthorizationModelsResponse = handle_response(res).await?;
+        Ok(models.authorization_models.into_iter().next())
+    }
+
+    #[tracing::instrument(skip(self, model))]
+    pub async fn write_authorization_model(
+        &self,
+        model: &AuthorizationModel,
+    ) -> Result<WriteAuthorizationModelResponse, Error> {
+        let req = self
+            .post(self.store_url("authorization-models")?)
+            .json::<AuthorizationModel>(model);
+        tracing::debug!("request being sent: {:?}", req);
+        let res = req.send().await?;
+        tracing::debug!("response body: {:?}", res);
+        handle_response(res).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn write_relationship_tuple(
+        &self,
+        relationship_action: RelationshipAction,
+    ) -> Result<WriteRelationshipTupleResponse, Error> {
+        let objects: HashSet<String> = match &relationship_action {
+            RelationshipAction::Writes(keys) | RelationshipAction::Deletes(keys) => keys
+                .tuple_keys
+                .iter()
+                .map(|tuple| tuple.object.clone())
+                .collect(),
+        };
+        let body = WriteRelationshipTupleSchema {
+            authorization_model_id: self.model_id()?,
+            relationship_action,
+        };
+        let req = self
+            .post(self.store_url("write")?)
+            .json::<WriteRelationshipTupleSchema>(&body);
+        tracing::debug!("request being sent: {:?}", req);
+        let res = req.send().await?;
+        tracing::debug!("response body: {:?}", res);
+        let response = handle_response(res).await?;
+        if let Some(cache) = &self.check_cache {
+            cache.invalidate_objects(objects);
+        }
+        Ok(response)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn check(&self, tuple: RelationshipTuple) -> Result<CheckResponse, Error> {
+        self.check_with_options(tuple, CheckOptions::default())
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn check_with_options(
+        &self,
+        tuple: RelationshipTuple,
+        options: CheckOptions,
+    ) -> Result<CheckResponse, Error> {
+        let cache = self.check_cache_for(&options);
+        let key = self.cache_key(&tuple)?;
+        if let Some(allowed) = cache.and_then(|cache| cache.get(&key)) {
+            return Ok(CheckResponse { allowed });
+        }
+
+        let (contextual_tuples, context, consistency) = options.into_parts();
+        let body = CheckRequest {
+            authorization_model_id: key.authorization_model_id.clone(),
+            tuple_key: tuple,
+            contextual_tuples,
+            context,
+            consistency,
+        };
+        let req = self
+            .post(self.store_url("check")?)
+            .json::<CheckRequest>(&body);
+        tracing::debug!("request being sent: {:?}", req);
+        let res = req.send().await?;
+        tracing::debug!("response body: {:?}", res);
+        let response = handle_response::<CheckResponse>(res).await?;
+        if let Some(cache) = cache {
+            cache.insert(key, response.allowed);
+        }
+        Ok(response)
+    }
+
+    /// Checks many tuples with as few round trips as possible. The same
+    /// `options` apply to every tuple. A tuple that OpenFGA could not evaluate
+    /// is logged and reported as not allowed.
+    #[tracing::instrument(skip(self))]
+    pub async fn batch_check(
+        &self,
+        tuples: Vec<RelationshipTuple>,
+        options: CheckOptions,
+    ) -> Result<HashMap<RelationshipTuple, bool>, Error> {
+        let authorization_model_id = self.model_id()?;
+        let url = self.store_url("batch-check")?;
+        let cache = self.check_cache_for(&options);
+        let (contextual_tuples, context, consistency) = options.into_parts();
+        let mut results = HashMap::with_capacity(tuples.len());
+
+        let mut misses = vec![];
+        for tuple in tuples {
+            match cache.and_then(|cache| cache.get(&self.cache_key(&tuple).ok()?)) {
+                Some(allowed) => {
+                    results.insert(tuple, allowed);
+                }
+                None => misses.push(tuple),
+            }
+        }
+
+        for chunk in misses.chunks(MAX_CHECKS_PER_BATCH) {
+            let body = BatchCheckRequest {
+                authorization_model_id: authorization_model_id.clone(),
+                checks: chunk
+                    .iter()
+                    .enumerate()
+                    .map(|(i, tuple)| BatchCheckItem {
+                        tuple_key: tuple.clone(),
+                        contextual_tuples: contextual_tuples.clone(),
+                        context: context.clone(),
+                        correlation_id: i.to_string(),
+                    })
+                    .collect(),
+                consistency,
+            };
+            let req = self.post(url.clone()).json::<BatchCheckRequest>(&body);
+            tracing::debug!("request being sent: {:?}", req);
+            let res = req.send().await?;
+            tracing::debug!("response body: {:?}", res);
+            let mut response = handle_response::<BatchCheckResponse>(res).await?;
+
+            for (i, tuple) in chunk.iter().enumerate() {
+                let allowed = match response.result.remove(&i.to_string()) {
+                    Some(BatchCheckSingleResult {
+                        allowed,
+                        error: None,
+                    }) => {
+                        if let Some(cache) = cache {
+                            cache.insert(self.cache_key(tuple)?, allowed);
+                        }
+                        allowed
+                    }
+                    Some(BatchCheckSingleResult {
+                        error: Some(error), ..
+                    }) => {
+                        tracing::error!("OpenFGA batch check failed for {:?}: {}", tuple, error);
+                        false
+                    }
+                    None => {
+                        tracing::error!("OpenFGA batch check returned no result for {:?}", tuple);
+                        false
+                    }
+                };
+                results.insert(tuple.clone(), allowed);
+            }
+        }
+        Ok(results)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn list_objects(
+        &self,
+        user: &str,
+        relation: &str,
+        object_type: &str,
+    ) -> Result<ListObjectsResponse, Error> {
+        let body = ListObjectsRequest {
+            authorization_model_id: self.model_id()?,
+            object_type: object_type.to_string(),
+            relation: relation.to_string(),
+            user: user.to_string(),
+        };
+        let req = self
+            .post(self.store_url("list-objects")?)
+            .json::<ListObjectsRequest>(&body);
+        tracing::debug!("request being sent: {:?}", req);
+        let res = req.send().await?;
+        tracing::debug!("response body: {:?}", res);
+        handle_response(res).await
+    }
+
+    /// Reads a single page of tuples. Pass the returned `continuation_token`
+    /// back in the next [`ReadRequest`] to fetch the following page.
+    #[tracing::instrument(skip(self))]
+    pub async fn read(&self, request: ReadRequest) -> Result<ReadResponse, Error> {
+        let req = self
+            .post(self.store_url("read")?)
+            .json::<ReadRequest>(&request);
+        tracing::debug!("request being sent: {:?}", req);
+        let res = req.send().await?;
+        tracing::debug!("response body: {:?}", res);
+        handle_response(res).await
+    }
+
+    /// Follows continuation tokens until every tuple matching `tuple_key` is read.
+    #[tracing::instrument(skip(self))]
+    pub async fn read_all(&self, tuple_key: Option<ReadTupleKey>) -> Result<Vec<Tuple>, Error> {
+        let mut tuples = vec![];
+        let mut continuation_token = None;
+        loop {
+            let page = self
+                .read(ReadRequest {
+                    tuple_key: tuple_key.clone(),
+                    page_size: None,
+                    continuation_token,
+                })
+                .await?;
+            tuples.extend(page.tuples);
+            if page.continuation_token.is_empty() {
+                return Ok(tuples);
+            }
+            continuation_token = Some(page.continuation_token);
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn expand(&self, relation: &str, object: &str) -> Result<ExpandResponse, Error> {
+        let body = ExpandRequest {
+            authorization_model_id: self.model_id()?,
+            tuple_key: ExpandTupleKey {
+                relation: relation.to_string(),
+                object: object.to_string(),
+            },
+        };
+        let req = self
+            .post(self.store_url("expand")?)
+            .json::<ExpandRequest>(&body);
+        tracing::debug!("request being sent: {:?}", req);
+        let res = req.send().await?;
+        tracing::debug!("response body: {:?}", res);
+        handle_response(res).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn list_users(
+        &self,
+        object: FgaObject,
+        relation: &str,
+        user_filters: Vec<UserTypeFilter>,
+    ) -> Result<ListUsersResponse, Error> {
+        let body = ListUsersRequest {
+            authorization_model_id: self.model_id()?,
+            object,
+            relation: relation.to_string(),
+            user_filters,
+        };
+        let req = self
+            .post(self.store_url("list-users")?)
+            .json::<ListUsersRequest>(&body);
+        tracing::debug!("request being sent: {:?}", req);
+        let res = req.send().await?;
+        tracing::debug!("response body: {:?}", res);
+        handle_response(res).await
     }
-    */
+}
+
+/// The subset of OpenFGA the app depends on, so tests can swap the HTTP
+/// client for [`InMemoryOpenFga`](crate::openfga_memory::InMemoryOpenFga).
+#[async_trait]
+pub trait Authorizer: std::fmt::Debug + Send + Sync {
+    async fn check_with_options(
+        &self,
+        tuple: RelationshipTuple,
+        options: CheckOptions,
+    ) -> Result<CheckResponse, Error>;
+
+    async fn write_relationship_tuple(
+        &self,
+        relationship_action: RelationshipAction,
+    ) -> Result<WriteRelationshipTupleResponse, Error>;
+
+    async fn check(&self, tuple: RelationshipTuple) -> Result<CheckResponse, Error> {
+        self.check_with_options(tuple, CheckOptions::default())
+            .await
+    }
+}
+
+#[async_trait]
+impl Authorizer for OpenFgaClient {
+    async fn check_with_options(
+        &self,
+        tuple: RelationshipTuple,
+        options: CheckOptions,
+    ) -> Result<CheckResponse, Error> {
+        OpenFgaClient::check_with_options(self, tuple, options).await
+    }
+
+    async fn write_relationship_tuple(
+        &self,
+        relationship_action: RelationshipAction,
+    ) -> Result<WriteRelationshipTupleResponse, Error> {
+        OpenFgaClient::write_relationship_tuple(self, relationship_action).await
+    }
+}
+
+/// Error body returned by OpenFGA for non-2xx responses, e.g.
+/// {"code":"validation_error","message":"invalid relation"}
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct OpenFgaErrorResponse {
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    message: String,
+}
+
+async fn handle_response<T: DeserializeOwned>(res: reqwest::Response) -> Result<T, Error> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res.json::<T>().await?);
+    }
+
+    let body = res.text().await?;
+    let error = serde_json::from_str::<OpenFgaErrorResponse>(&body).unwrap_or_else(|_| {
+        OpenFgaErrorResponse {
+            code: String::new(),
+            message: body,
+        }
+    });
+    tracing::debug!("OpenFGA error response: {:?}", error);
+    Err(Error::OpenFga {
+        status: status.as_u16(),
+        code: error.code,
+        message: error.message,
+    })
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -98,43 +616,51 @@ pub struct CreateDataStoreSchema {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct CreateDataStoreResponse {
+pub struct Store {
     pub id: String,
     pub name: String,
     pub created_at: String,
     pub updated_at: String,
-    // {"id":"01HJ3FP23AS376NVDBECMZBAPT", "name":"FGA Demo Store", "created_at":"2023-12-20T11:27:27.850720014Z", "updated_at":"2023-12-20T11:27:27.850720014Z"}%
 }
 
-#[tracing::instrument]
-pub async fn create_data_store(
-    store: CreateDataStoreSchema,
-    headers: Option<HeaderMap>,
-) -> Result<CreateDataStoreResponse, Error> {
-    let http_client = get_client();
-    //let fga_base_url = std::env::var("FGA_BASE_URL").expect("Define FGA_BASE_URL");
-    let fga_base_url = "http://127.0.0.1:8080";
-    let trace_headers = get_trace_info();
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListStoresResponse {
+    pub stores: Vec<Store>,
+    #[serde(default)]
+    pub continuation_token: String,
+}
 
-    let req = http_client
-        .post(format!("{}/stores", fga_base_url))
-        .headers(headers.unwrap_or_default())
-        .headers(trace_headers)
-        .json::<CreateDataStoreSchema>(&store);
-    tracing::debug!("request being sent: {:?}", req);
-    let res = req.send().await?;
-    tracing::debug!("response body: {:?}", res);
-    Ok(res.json::<CreateDataStoreResponse>().await?)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StoredAuthorizationModel {
+    pub id: String,
+    #[serde(flatten)]
+    pub model: AuthorizationModel,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct RelationshipTuple {
-    user: String,
-    relation: String,
-    object: String,
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReadAuthorizationModelsResponse {
+    pub authorization_models: Vec<StoredAuthorizationModel>,
+    #[serde(default)]
+    pub continuation_token: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+pub struct CreateDataStoreResponse {
+    pub id: String,
+    pub name: String,
+    pub created_at: String,
+    pub updated_at: String,
+    // {"id":"01HJ3FP23AS376NVDBECMZBAPT", "name":"FGA Demo Store", "created_at":"2023-12-20T11:27:27.850720014Z", "updated_at":"2023-12-20T11:27:27.850720014Z"}%
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RelationshipTuple {
+    pub user: String,
+    pub relation: String,
+    pub object: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TupleKeys {
     pub tuple_keys: Vec<RelationshipTuple>,
 }
@@ -158,102 +684,280 @@ pub struct WriteRelationshipTupleResponse {
     // {}%
 }
 
-#[tracing::instrument]
-pub async fn write_relationship_tuple(
-    store_id: String,
-    tuples: WriteRelationshipTupleSchema,
-    headers: Option<HeaderMap>,
-) -> Result<WriteRelationshipTupleResponse, Error> {
-    let http_client = get_client();
-    //let fga_base_url = std::env::var("FGA_BASE_URL").expect("Define FGA_BASE_URL");
-    let fga_base_url = "http://127.0.0.1:8080";
-    let trace_headers = get_trace_info();
-
-    let req = http_client
-        .post(format!("{}/stores/{}/write", fga_base_url, store_id))
-        .headers(headers.unwrap_or_default())
-        .headers(trace_headers)
-        .json::<WriteRelationshipTupleSchema>(&tuples);
-    tracing::debug!("request being sent: {:?}", req);
-    let res = req.send().await?;
-    tracing::debug!("response body: {:?}", res);
-    println!("{:?}", res);
-    Ok(res.json::<WriteRelationshipTupleResponse>().await?)
-}
-
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct WriteAuthorizationModelResponse {
     // {"authorization_model_id":"01HJ8QTTREQ7QJ9P5BCK0RHH5F"}
+    pub authorization_model_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CheckRequest {
     authorization_model_id: String,
+    tuple_key: RelationshipTuple,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contextual_tuples: Option<TupleKeys>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    consistency: Option<Consistency>,
 }
 
-#[tracing::instrument]
-pub async fn write_authorization_model(
-    store_id: String,
-    model: String,
-    headers: Option<HeaderMap>,
-) -> Result<WriteAuthorizationModelResponse, Error> {
-    let http_client = get_client();
-    //let fga_base_url = std::env::var("FGA_BASE_URL").expect("Define FGA_BASE_URL");
-    let fga_base_url = "http://127.0.0.1:8080";
-    let trace_headers = get_trace_info();
+/// Extra inputs for a check: tuples that only exist for this request, the
+/// `context` object evaluated by ABAC conditions, and how fresh the answer
+/// must be.
+#[derive(Debug, Clone, Default)]
+pub struct CheckOptions {
+    pub contextual_tuples: Vec<RelationshipTuple>,
+    pub context: Option<Value>,
+    pub consistency: Consistency,
+}
 
-    let v: Value = serde_json::from_str(&model)?;
+impl CheckOptions {
+    /// Contextual tuples and context change the answer per request, so only
+    /// plain checks are cached.
+    fn is_cacheable(&self) -> bool {
+        self.consistency == Consistency::MinimizeLatency
+            && self.contextual_tuples.is_empty()
+            && self.context.is_none()
+    }
 
-    let req = http_client
-        .post(format!(
-            "{}/stores/{}/authorization-models",
-            fga_base_url, store_id
-        ))
-        .headers(headers.unwrap_or_default())
-        .headers(trace_headers)
-        .json::<Value>(&v);
-    tracing::debug!("request being sent: {:?}", req);
-    let res = req.send().await?;
-    tracing::debug!("response body: {:?}", res);
-    println!("{:?}", res);
-    Ok(res.json::<WriteAuthorizationModelResponse>().await?)
+    fn into_parts(self) -> (Option<TupleKeys>, Option<Value>, Option<Consistency>) {
+        let contextual_tuples = (!self.contextual_tuples.is_empty()).then_some(TupleKeys {
+            tuple_keys: self.contextual_tuples,
+        });
+        let consistency =
+            (self.consistency == Consistency::HigherConsistency).then_some(self.consistency);
+        (contextual_tuples, self.context, consistency)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Consistency {
+    /// May be answered from our check cache or OpenFGA's own caches.
+    #[default]
+    MinimizeLatency,
+    /// Bypasses every cache and reads the latest tuples.
+    HigherConsistency,
 }
 
+/// OpenFGA rejects batch checks with more than 50 items by default.
+const MAX_CHECKS_PER_BATCH: usize = 50;
+
 #[derive(Serialize, Deserialize, Debug)]
-pub struct CheckRequest {
-    // {"allowed":true}
-    authorization_model_id: String,
+pub struct BatchCheckItem {
     tuple_key: RelationshipTuple,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contextual_tuples: Option<TupleKeys>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<Value>,
+    correlation_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchCheckRequest {
+    authorization_model_id: String,
+    checks: Vec<BatchCheckItem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    consistency: Option<Consistency>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchCheckResponse {
+    // {"result":{"0":{"allowed":true},"1":{"error":{"input_error":"validation_error","message":"..."}}}}
+    pub result: HashMap<String, BatchCheckSingleResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchCheckSingleResult {
+    #[serde(default)]
+    pub allowed: bool,
+    pub error: Option<Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct CheckResponse {
     // {"allowed":true}
-    allowed: bool,
-}
-
-#[tracing::instrument]
-pub async fn check(
-    store_id: String,
-    model_id: String,
-    tuple: RelationshipTuple,
-    headers: Option<HeaderMap>,
-) -> Result<CheckResponse, Error> {
-    let http_client = get_client();
-    //let fga_base_url = std::env::var("FGA_BASE_URL").expect("Define FGA_BASE_URL");
-    let fga_base_url = "http://127.0.0.1:8080";
-    let trace_headers = get_trace_info();
-
-    let body = CheckRequest {
-        authorization_model_id: model_id,
-        tuple_key: tuple,
-    };
-    let req = http_client
-        .post(format!("{}/stores/{}/check", fga_base_url, store_id))
-        .headers(headers.unwrap_or_default())
-        .headers(trace_headers)
-        .json::<CheckRequest>(&body);
-    tracing::debug!("request being sent: {:?}", req);
-    let res = req.send().await?;
-    tracing::debug!("response body: {:?}", res);
-    println!("{:?}", res);
-    Ok(res.json::<CheckResponse>().await?)
+    pub allowed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListObjectsRequest {
+    authorization_model_id: String,
+    #[serde(rename = "type")]
+    object_type: String,
+    relation: String,
+    user: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ListObjectsResponse {
+    // {"objects":["repo:website","repo:api"]}
+    pub objects: Vec<String>,
+}
+
+/// Filter for [`OpenFgaClient::read`]. `object` may be just a type (`"repo:"`)
+/// when `user` is set.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ReadTupleKey {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relation: Option<String>,
+    pub object: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ReadRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tuple_key: Option<ReadTupleKey>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_size: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continuation_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Tuple {
+    pub key: RelationshipTuple,
+    pub timestamp: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReadResponse {
+    // {"tuples":[{"key":{"user":"user:anne","relation":"reader","object":"repo:api"},"timestamp":"2023-12-20T11:27:27.850720014Z"}],"continuation_token":""}
+    pub tuples: Vec<Tuple>,
+    /// Empty when there are no more pages.
+    #[serde(default)]
+    pub continuation_token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExpandTupleKey {
+    relation: String,
+    object: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExpandRequest {
+    authorization_model_id: String,
+    tuple_key: ExpandTupleKey,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ExpandResponse {
+    pub tree: UsersetTree,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct UsersetTree {
+    pub root: Node,
+}
+
+/// A node of the userset tree returned by expand, e.g.
+/// {"name":"repo:api#reader","union":{"nodes":[...]}}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Node {
+    pub name: String,
+    #[serde(flatten)]
+    pub value: NodeValue,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeValue {
+    Leaf(Leaf),
+    Difference(Difference),
+    Union(Nodes),
+    Intersection(Nodes),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Nodes {
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub base: Box<Node>,
+    pub subtract: Box<Node>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum Leaf {
+    #[serde(rename = "users")]
+    Users(Users),
+    #[serde(rename = "computed")]
+    Computed(Computed),
+    #[serde(rename = "tupleToUserset")]
+    TupleToUserset(TupleToUserset),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Users {
+    pub users: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Computed {
+    pub userset: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct TupleToUserset {
+    pub tupleset: String,
+    pub computed: Vec<Computed>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FgaObject {
+    #[serde(rename = "type")]
+    pub object_type: String,
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UserTypeFilter {
+    #[serde(rename = "type")]
+    pub user_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relation: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListUsersRequest {
+    authorization_model_id: String,
+    object: FgaObject,
+    relation: String,
+    user_filters: Vec<UserTypeFilter>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ListUsersResponse {
+    // {"users":[{"object":{"type":"user","id":"anne"}},{"userset":{"type":"team","id":"core","relation":"member"}}]}
+    pub users: Vec<ListedUser>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ListedUser {
+    Object(FgaObject),
+    Userset(UsersetUser),
+    Wildcard(TypedWildcard),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct UsersetUser {
+    #[serde(rename = "type")]
+    pub user_type: String,
+    pub id: String,
+    pub relation: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct TypedWildcard {
+    #[serde(rename = "type")]
+    pub user_type: String,
 }
 
 pub fn make_tuple(user: &str, relation: &str, object: &str) -> RelationshipTuple {
@@ -267,43 +971,38 @@ pub fn make_tuple(user: &str, relation: &str, object: &str) -> RelationshipTuple
 #[cfg(test)]
 mod tests {
     use crate::openfga::*;
+    use crate::openfga_cache::CheckCache;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
     #[tokio::test]
+    #[ignore = "needs an OpenFGA server on 127.0.0.1:8080, see docker-compose.yaml"]
     async fn test_openfga_create_data_store() {
+        let client = OpenFgaClient::new("http://127.0.0.1:8080");
         let store_name = "foobar2".to_string();
-        let res = create_data_store(
-            CreateDataStoreSchema {
+        let res = client
+            .create_data_store(CreateDataStoreSchema {
                 name: store_name.clone(),
-            },
-            None,
-        )
-        .await;
+            })
+            .await;
 
         let store = res.unwrap();
         assert_eq!(store.name, store_name);
+        let client = client.with_store_id(store.id.clone());
 
-        let model_string = r#"
-        {"schema_version":"1.1","type_definitions":[{"type":"user"},{"type":"document","relations":{"reader":{"this":{}},"writer":{"this":{}},"owner":{"this":{}}},"metadata":{"relations":{"reader":{"directly_related_user_types":[{"type":"user"}]},"writer":{"directly_related_user_types":[{"type":"user"}]},"owner":{"directly_related_user_types":[{"type":"user"}]}}}}]}
-        "#.to_string();
-        let model = write_authorization_model(store.id.clone(), model_string, None).await;
+        let model = AuthorizationModel::from_dsl(include_str!("../../simple-model.txt")).unwrap();
+        let model = client.write_authorization_model(&model).await;
         let authorization_model_id = model.unwrap().authorization_model_id;
+        let client = client.with_authorization_model_id(authorization_model_id);
         let tuple = make_tuple("user:789", "reader", "document:z");
-        let json = WriteRelationshipTupleSchema {
-            authorization_model_id: authorization_model_id.clone(),
-            relationship_action: RelationshipAction::Writes(TupleKeys {
+        let res = client
+            .write_relationship_tuple(RelationshipAction::Writes(TupleKeys {
                 tuple_keys: vec![tuple.clone()],
-            }),
-        };
-        println!("{:?}", serde_json::to_string(&json));
-        let res = write_relationship_tuple(store.id.clone(), json, None).await;
+            }))
+            .await;
         assert_eq!(res.unwrap(), WriteRelationshipTupleResponse {});
-        let allowed = check(
-            store.id.clone(),
-            authorization_model_id.clone(),
-            tuple,
-            None,
-        )
-        .await;
+        let allowed = client.check(tuple).await;
         assert_eq!(allowed.unwrap().allowed, true);
     }
 
@@ -322,4 +1021,459 @@ mod tests {
         println!("{:?}", serde_json::to_string(&json));
         assert_eq!(true, true);
     }
+
+    async fn mock_client() -> (MockServer, OpenFgaClient) {
+        let server = MockServer::start().await;
+        let client = OpenFgaClient::new(server.uri())
+            .with_store_id("store-1")
+            .with_authorization_model_id("model-1");
+        (server, client)
+    }
+
+    #[tokio::test]
+    async fn test_openfga_list_objects() {
+        let (server, client) = mock_client().await;
+        Mock::given(method("POST"))
+            .and(path("/stores/store-1/list-objects"))
+            .and(body_json(json!({
+                "authorization_model_id": "model-1",
+                "type": "repo",
+                "relation": "reader",
+                "user": "user:anne",
+            })))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({"objects": ["repo:api", "repo:website"]})),
+            )
+            .mount(&server)
+            .await;
+
+        let res = client.list_objects("user:anne", "reader", "repo").await;
+        assert_eq!(
+            res.unwrap(),
+            ListObjectsResponse {
+                objects: vec!["repo:api".to_string(), "repo:website".to_string()]
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_openfga_read_all_follows_continuation_token() {
+        let (server, client) = mock_client().await;
+        Mock::given(method("POST"))
+            .and(path("/stores/store-1/read"))
+            .and(body_json(json!({"tuple_key": {"object": "repo:api"}})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "tuples": [{
+                    "key": {"user": "user:anne", "relation": "reader", "object": "repo:api"},
+                    "timestamp": "2023-12-20T11:27:27.850720014Z"
+                }],
+                "continuation_token": "page-2"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/stores/store-1/read"))
+            .and(body_json(json!({
+                "tuple_key": {"object": "repo:api"},
+                "continuation_token": "page-2"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "tuples": [{
+                    "key": {"user": "team:core#member", "relation": "writer", "object": "repo:api"},
+                    "timestamp": "2023-12-20T11:27:28.850720014Z"
+                }],
+                "continuation_token": ""
+            })))
+            .mount(&server)
+            .await;
+
+        let tuples = client
+            .read_all(Some(ReadTupleKey {
+                object: "repo:api".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        let users: Vec<&str> = tuples.iter().map(|t| t.key.user.as_str()).collect();
+        assert_eq!(users, vec!["user:anne", "team:core#member"]);
+    }
+
+    #[tokio::test]
+    async fn test_openfga_expand() {
+        let (server, client) = mock_client().await;
+        Mock::given(method("POST"))
+            .and(path("/stores/store-1/expand"))
+            .and(body_json(json!({
+                "authorization_model_id": "model-1",
+                "tuple_key": {"relation": "reader", "object": "repo:api"}
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "tree": {"root": {
+                    "name": "repo:api#reader",
+                    "union": {"nodes": [
+                        {"name": "repo:api#reader", "leaf": {"users": {"users": ["user:anne"]}}},
+                        {"name": "repo:api#reader", "leaf": {"computed": {"userset": "repo:api#triager"}}},
+                        {"name": "repo:api#reader", "leaf": {"tupleToUserset": {
+                            "tupleset": "repo:api#owner",
+                            "computed": [{"userset": "organization:acme#repo_reader"}]
+                        }}}
+                    ]}
+                }}
+            })))
+            .mount(&server)
+            .await;
+
+        let tree = client.expand("reader", "repo:api").await.unwrap().tree;
+        let NodeValue::Union(union) = tree.root.value else {
+            panic!("expected a union node");
+        };
+        assert_eq!(
+            union.nodes[0].value,
+            NodeValue::Leaf(Leaf::Users(Users {
+                users: vec!["user:anne".to_string()]
+            }))
+        );
+        assert_eq!(
+            union.nodes[2].value,
+            NodeValue::Leaf(Leaf::TupleToUserset(TupleToUserset {
+                tupleset: "repo:api#owner".to_string(),
+                computed: vec![Computed {
+                    userset: "organization:acme#repo_reader".to_string()
+                }],
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_openfga_list_users() {
+        let (server, client) = mock_client().await;
+        Mock::given(method("POST"))
+            .and(path("/stores/store-1/list-users"))
+            .and(body_json(json!({
+                "authorization_model_id": "model-1",
+                "object": {"type": "repo", "id": "api"},
+                "relation": "reader",
+                "user_filters": [{"type": "user"}, {"type": "team", "relation": "member"}]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "users": [
+                    {"object": {"type": "user", "id": "anne"}},
+                    {"userset": {"type": "team", "id": "core", "relation": "member"}},
+                    {"wildcard": {"type": "user"}}
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let res = client
+            .list_users(
+                FgaObject {
+                    object_type: "repo".to_string(),
+                    id: "api".to_string(),
+                },
+                "reader",
+                vec![
+                    UserTypeFilter {
+                        user_type: "user".to_string(),
+                        relation: None,
+                    },
+                    UserTypeFilter {
+                        user_type: "team".to_string(),
+                        relation: Some("member".to_string()),
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            res.users,
+            vec![
+                ListedUser::Object(FgaObject {
+                    object_type: "user".to_string(),
+                    id: "anne".to_string(),
+                }),
+                ListedUser::Userset(UsersetUser {
+                    user_type: "team".to_string(),
+                    id: "core".to_string(),
+                    relation: "member".to_string(),
+                }),
+                ListedUser::Wildcard(TypedWildcard {
+                    user_type: "user".to_string(),
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_openfga_error_response() {
+        let (server, client) = mock_client().await;
+        Mock::given(method("POST"))
+            .and(path("/stores/store-1/list-objects"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "code": "validation_error",
+                "message": "type 'nope' not found"
+            })))
+            .mount(&server)
+            .await;
+
+        let res = client.list_objects("user:anne", "reader", "nope").await;
+        match res {
+            Err(Error::OpenFga { status, code, .. }) => {
+                assert_eq!(status, 400);
+                assert_eq!(code, "validation_error");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openfga_check_with_contextual_tuples() {
+        let (server, client) = mock_client().await;
+        Mock::given(method("POST"))
+            .and(path("/stores/store-1/check"))
+            .and(body_json(json!({
+                "authorization_model_id": "model-1",
+                "tuple_key": {"user": "user:anne", "relation": "reader", "object": "repo:api"},
+                "contextual_tuples": {"tuple_keys": [
+                    {"user": "user:anne", "relation": "member", "object": "team:core"}
+                ]},
+                "context": {"ip_address": "10.0.0.1"}
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"allowed": true})))
+            .mount(&server)
+            .await;
+
+        let res = client
+            .check_with_options(
+                make_tuple("user:anne", "reader", "repo:api"),
+                CheckOptions {
+                    contextual_tuples: vec![make_tuple("user:anne", "member", "team:core")],
+                    context: Some(json!({"ip_address": "10.0.0.1"})),
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert_eq!(res.unwrap(), CheckResponse { allowed: true });
+    }
+
+    #[tokio::test]
+    async fn test_openfga_batch_check() {
+        let (server, client) = mock_client().await;
+        Mock::given(method("POST"))
+            .and(path("/stores/store-1/batch-check"))
+            .and(body_json(json!({
+                "authorization_model_id": "model-1",
+                "checks": [
+                    {
+                        "tuple_key": {"user": "user:anne", "relation": "reader", "object": "repo:api"},
+                        "correlation_id": "0"
+                    },
+                    {
+                        "tuple_key": {"user": "user:anne", "relation": "reader", "object": "repo:website"},
+                        "correlation_id": "1"
+                    },
+                    {
+                        "tuple_key": {"user": "user:anne", "relation": "reader", "object": "repo:infra"},
+                        "correlation_id": "2"
+                    }
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "result": {
+                    "0": {"allowed": true},
+                    "1": {"allowed": false},
+                    "2": {"error": {"input_error": "validation_error", "message": "invalid object"}}
+                }
+            })))
+            .mount(&server)
+            .await;
+
+        let api = make_tuple("user:anne", "reader", "repo:api");
+        let website = make_tuple("user:anne", "reader", "repo:website");
+        let infra = make_tuple("user:anne", "reader", "repo:infra");
+        let res = client
+            .batch_check(
+                vec![api.clone(), website.clone(), infra.clone()],
+                CheckOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 3);
+        assert!(res[&api]);
+        assert!(!res[&website]);
+        assert!(!res[&infra]);
+    }
+
+    fn cached_client(client: OpenFgaClient) -> OpenFgaClient {
+        client.with_check_cache(CheckCache::new(std::time::Duration::from_secs(60), 100))
+    }
+
+    async fn mock_check(server: &MockServer, object: &str, allowed: bool, expected_calls: u64) {
+        Mock::given(method("POST"))
+            .and(path("/stores/store-1/check"))
+            .and(body_partial_json(json!({
+                "tuple_key": {"user": "user:anne", "relation": "reader", "object": object}
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"allowed": allowed})))
+            .expect(expected_calls)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_openfga_check_cache_hit() {
+        let (server, client) = mock_client().await;
+        let client = cached_client(client);
+        mock_check(&server, "repo:api", true, 1).await;
+
+        let tuple = make_tuple("user:anne", "reader", "repo:api");
+        assert!(client.check(tuple.clone()).await.unwrap().allowed);
+        assert!(client.check(tuple).await.unwrap().allowed);
+    }
+
+    #[tokio::test]
+    async fn test_openfga_check_cache_bypassed_for_higher_consistency() {
+        let (server, client) = mock_client().await;
+        let client = cached_client(client);
+        mock_check(&server, "repo:api", true, 2).await;
+
+        let tuple = make_tuple("user:anne", "reader", "repo:api");
+        let options = CheckOptions {
+            consistency: Consistency::HigherConsistency,
+            ..Default::default()
+        };
+        assert!(client.check(tuple.clone()).await.unwrap().allowed);
+        let res = client.check_with_options(tuple, options).await;
+        assert!(res.unwrap().allowed);
+    }
+
+    #[tokio::test]
+    async fn test_openfga_write_invalidates_cached_checks() {
+        let (server, client) = mock_client().await;
+        let client = cached_client(client);
+        mock_check(&server, "repo:api", true, 2).await;
+        mock_check(&server, "repo:website", true, 1).await;
+        Mock::given(method("POST"))
+            .and(path("/stores/store-1/write"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&server)
+            .await;
+
+        let api = make_tuple("user:anne", "reader", "repo:api");
+        let website = make_tuple("user:anne", "reader", "repo:website");
+        client.check(api.clone()).await.unwrap();
+        client.check(website.clone()).await.unwrap();
+        client
+            .write_relationship_tuple(RelationshipAction::Deletes(TupleKeys {
+                tuple_keys: vec![make_tuple("user:bob", "reader", "repo:api")],
+            }))
+            .await
+            .unwrap();
+        client.check(api).await.unwrap();
+        client.check(website).await.unwrap();
+    }
+
+    const PROVISION_MODEL: &str = "model
+  schema 1.1
+
+type user
+
+type doc
+  relations
+    define viewer: [user]
+";
+
+    fn store_json(id: &str, name: &str) -> Value {
+        json!({
+            "id": id,
+            "name": name,
+            "created_at": "2024-02-15T09:00:00Z",
+            "updated_at": "2024-02-15T09:00:00Z"
+        })
+    }
+
+    #[tokio::test]
+    async fn test_openfga_provision_creates_store_and_model() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/stores"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "stores": [store_json("store-1", "other")],
+                "continuation_token": ""
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/stores"))
+            .and(body_json(json!({"name": "rust-template"})))
+            .respond_with(
+                ResponseTemplate::new(201).set_body_json(store_json("store-2", "rust-template")),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/stores/store-2/authorization-models"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({"authorization_models": []})),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/stores/store-2/authorization-models"))
+            .respond_with(
+                ResponseTemplate::new(201)
+                    .set_body_json(json!({"authorization_model_id": "model-2"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let model = AuthorizationModel::from_dsl(PROVISION_MODEL).unwrap();
+        let client = OpenFgaClient::new(server.uri())
+            .provision("rust-template", &model)
+            .await
+            .unwrap();
+        assert_eq!(client.store_id(), Some("store-2"));
+        assert_eq!(client.authorization_model_id(), Some("model-2"));
+    }
+
+    #[tokio::test]
+    async fn test_openfga_provision_keeps_unchanged_model() {
+        let server = MockServer::start().await;
+        let model = AuthorizationModel::from_dsl(PROVISION_MODEL).unwrap();
+        // OpenFGA echoes empty metadata and conditions back
+        let mut latest = serde_json::to_value(&model).unwrap();
+        latest["id"] = json!("model-1");
+        latest["conditions"] = json!({});
+        latest["type_definitions"][0]["metadata"] = json!({"relations": {}, "module": ""});
+        Mock::given(method("GET"))
+            .and(path("/stores"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "stores": [store_json("store-1", "rust-template")],
+                "continuation_token": ""
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/stores/store-1/authorization-models"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({"authorization_models": [latest]})),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let client = OpenFgaClient::new(server.uri())
+            .provision("rust-template", &model)
+            .await
+            .unwrap();
+        assert_eq!(client.store_id(), Some("store-1"));
+        assert_eq!(client.authorization_model_id(), Some("model-1"));
+    }
 }
@@ -0,0 +1,89 @@
+use crate::openfga::RelationshipTuple;
+use moka::sync::Cache;
+use opentelemetry::{global, metrics::Counter, KeyValue};
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CheckCacheKey {
+    pub store_id: String,
+    pub authorization_model_id: String,
+    pub tuple: RelationshipTuple,
+}
+
+/// TTL and size bounded cache of `check` decisions.
+///
+/// Writes through `OpenFgaClient::write_relationship_tuple` evict every
+/// decision about the written objects. Changes that only reach an object
+/// indirectly (e.g. a new team member) are picked up when the TTL expires, so
+/// use `Consistency::HigherConsistency` where that lag is not acceptable.
+#[derive(Debug)]
+pub struct CheckCache {
+    decisions: Cache<CheckCacheKey, bool>,
+    requests: OnceLock<Counter<u64>>,
+}
+
+impl CheckCache {
+    pub fn new(ttl: Duration, max_entries: u64) -> Self {
+        Self {
+            decisions: Cache::builder()
+                .time_to_live(ttl)
+                .max_capacity(max_entries)
+                .support_invalidation_closures()
+                .build(),
+            requests: OnceLock::new(),
+        }
+    }
+
+    /// Reads `FGA_CHECK_CACHE_TTL_SECONDS` and `FGA_CHECK_CACHE_MAX_ENTRIES`.
+    /// Returns `None`, i.e. no caching, unless a positive TTL is configured.
+    pub fn from_env() -> Option<Self> {
+        let ttl = std::env::var("FGA_CHECK_CACHE_TTL_SECONDS")
+            .ok()?
+            .parse::<u64>()
+            .expect("FGA_CHECK_CACHE_TTL_SECONDS must be a number");
+        if ttl == 0 {
+            return None;
+        }
+        let max_entries = std::env::var("FGA_CHECK_CACHE_MAX_ENTRIES")
+            .map(|v| {
+                v.parse::<u64>()
+                    .expect("FGA_CHECK_CACHE_MAX_ENTRIES must be a number")
+            })
+            .unwrap_or(10_000);
+        Some(Self::new(Duration::from_secs(ttl), max_entries))
+    }
+
+    pub fn get(&self, key: &CheckCacheKey) -> Option<bool> {
+        let allowed = self.decisions.get(key);
+        let result = if allowed.is_some() { "hit" } else { "miss" };
+        self.requests().add(1, &[KeyValue::new("result", result)]);
+        allowed
+    }
+
+    pub fn insert(&self, key: CheckCacheKey, allowed: bool) {
+        self.decisions.insert(key, allowed);
+    }
+
+    /// Evicts every cached decision about one of `objects`.
+    pub fn invalidate_objects(&self, objects: HashSet<String>) {
+        if objects.is_empty() {
+            return;
+        }
+        self.decisions
+            .invalidate_entries_if(move |key, _| objects.contains(&key.tuple.object))
+            .expect("invalidation closures are enabled");
+    }
+
+    // Created on first use: the global meter provider is only installed by
+    // `create_server`, after the app state (and this cache) has been built.
+    fn requests(&self) -> &Counter<u64> {
+        self.requests.get_or_init(|| {
+            global::meter("openfga")
+                .u64_counter("openfga.check.cache.requests")
+                .with_description("OpenFGA check cache lookups by result (hit or miss)")
+                .init()
+        })
+    }
+}
@@ -0,0 +1,404 @@
+//! An in-process stand-in for OpenFGA that evaluates an [`AuthorizationModel`]
+//! against tuples held in memory, so handlers and middleware can be tested
+//! without a running server.
+//!
+//! ```ignore
+//! let openfga = InMemoryOpenFga::new(AuthorizationModel::from_dsl(include_str!("../model.fga"))?)
+//!     .with_tuples([make_tuple("user:anne", "owner", "account:1")]);
+//! ```
+use crate::{
+    error::Error,
+    openfga::{
+        Authorizer, CheckOptions, CheckResponse, RelationshipAction, RelationshipTuple,
+        WriteRelationshipTupleResponse,
+    },
+    openfga_model::{AuthorizationModel, Userset},
+};
+use axum::async_trait;
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+#[derive(Debug)]
+pub struct InMemoryOpenFga {
+    model: AuthorizationModel,
+    tuples: RwLock<HashSet<RelationshipTuple>>,
+}
+
+impl InMemoryOpenFga {
+    pub fn new(model: AuthorizationModel) -> Self {
+        Self {
+            model,
+            tuples: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// Stores `tuples` without validating them against the model.
+    pub fn with_tuples(self, tuples: impl IntoIterator<Item = RelationshipTuple>) -> Self {
+        self.tuples.write().unwrap().extend(tuples);
+        self
+    }
+
+    pub fn tuples(&self) -> Vec<RelationshipTuple> {
+        self.tuples.read().unwrap().iter().cloned().collect()
+    }
+
+    fn userset(&self, object: &str, relation: &str) -> Result<&Userset, Error> {
+        let object_type = object_type(object)?;
+        self.model
+            .type_definitions
+            .iter()
+            .find(|type_definition| type_definition.type_name == object_type)
+            .ok_or_else(|| validation_error(format!("type '{}' not found", object_type)))?
+            .relations
+            .get(relation)
+            .ok_or_else(|| {
+                validation_error(format!("relation '{}#{}' not found", object_type, relation))
+            })
+    }
+}
+
+#[async_trait]
+impl Authorizer for InMemoryOpenFga {
+    async fn check_with_options(
+        &self,
+        tuple: RelationshipTuple,
+        options: CheckOptions,
+    ) -> Result<CheckResponse, Error> {
+        let mut tuples = self.tuples.read().unwrap().clone();
+        tuples.extend(options.contextual_tuples);
+        let evaluator = Evaluator {
+            store: self,
+            tuples: &tuples,
+        };
+        let allowed = evaluator.check(
+            &tuple.user,
+            &tuple.relation,
+            &tuple.object,
+            &mut HashSet::new(),
+        )?;
+        Ok(CheckResponse { allowed })
+    }
+
+    /// Applies all tuple changes or none, rejecting duplicate writes and
+    /// deletes of missing tuples like OpenFGA does.
+    async fn write_relationship_tuple(
+        &self,
+        relationship_action: RelationshipAction,
+    ) -> Result<WriteRelationshipTupleResponse, Error> {
+        let mut tuples = self.tuples.write().unwrap();
+        match relationship_action {
+            RelationshipAction::Writes(keys) => {
+                for tuple in &keys.tuple_keys {
+                    self.userset(&tuple.object, &tuple.relation)?;
+                    if tuples.contains(tuple) {
+                        return Err(write_error(
+                            "cannot write a tuple which already exists",
+                            tuple,
+                        ));
+                    }
+                }
+                tuples.extend(keys.tuple_keys);
+            }
+            RelationshipAction::Deletes(keys) => {
+                if let Some(tuple) = keys.tuple_keys.iter().find(|t| !tuples.contains(t)) {
+                    return Err(write_error(
+                        "cannot delete a tuple which does not exist",
+                        tuple,
+                    ));
+                }
+                for tuple in &keys.tuple_keys {
+                    tuples.remove(tuple);
+                }
+            }
+        }
+        Ok(WriteRelationshipTupleResponse {})
+    }
+}
+
+struct Evaluator<'a> {
+    store: &'a InMemoryOpenFga,
+    tuples: &'a HashSet<RelationshipTuple>,
+}
+
+impl Evaluator<'_> {
+    /// `visiting` holds the `(relation, object)` pairs on the current path, so
+    /// recursive definitions such as `member: [team#member]` terminate.
+    fn check(
+        &self,
+        user: &str,
+        relation: &str,
+        object: &str,
+        visiting: &mut HashSet<(String, String)>,
+    ) -> Result<bool, Error> {
+        let key = (relation.to_string(), object.to_string());
+        if !visiting.insert(key.clone()) {
+            return Ok(false);
+        }
+        let userset = self.store.userset(object, relation)?;
+        let allowed = self.eval(userset, user, relation, object, visiting);
+        visiting.remove(&key);
+        allowed
+    }
+
+    fn eval(
+        &self,
+        userset: &Userset,
+        user: &str,
+        relation: &str,
+        object: &str,
+        visiting: &mut HashSet<(String, String)>,
+    ) -> Result<bool, Error> {
+        match userset {
+            Userset::This(_) => {
+                for tuple in self.related(relation, object) {
+                    if tuple.user == user || is_wildcard_for(&tuple.user, user) {
+                        return Ok(true);
+                    }
+                    if let Some((set_object, set_relation)) = tuple.user.split_once('#') {
+                        if self.check(user, set_relation, set_object, visiting)? {
+                            return Ok(true);
+                        }
+                    }
+                }
+                Ok(false)
+            }
+            Userset::ComputedUserset(computed) => {
+                self.check(user, &computed.relation, object, visiting)
+            }
+            Userset::TupleToUserset(ttu) => {
+                for tuple in self.related(&ttu.tupleset.relation, object) {
+                    if tuple.user.contains('#') {
+                        continue;
+                    }
+                    // like OpenFGA, skip parents whose type lacks the relation
+                    if self
+                        .store
+                        .userset(&tuple.user, &ttu.computed_userset.relation)
+                        .is_err()
+                    {
+                        continue;
+                    }
+                    if self.check(user, &ttu.computed_userset.relation, &tuple.user, visiting)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Userset::Union(usersets) => {
+                for child in &usersets.child {
+                    if self.eval(child, user, relation, object, visiting)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Userset::Intersection(usersets) => {
+                for child in &usersets.child {
+                    if !self.eval(child, user, relation, object, visiting)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Userset::Difference(difference) => {
+                Ok(
+                    self.eval(&difference.base, user, relation, object, visiting)?
+                        && !self.eval(&difference.subtract, user, relation, object, visiting)?,
+                )
+            }
+        }
+    }
+
+    fn related<'b>(
+        &'b self,
+        relation: &'b str,
+        object: &'b str,
+    ) -> impl Iterator<Item = &'b RelationshipTuple> + 'b {
+        self.tuples
+            .iter()
+            .filter(move |tuple| tuple.relation == relation && tuple.object == object)
+    }
+}
+
+/// `user:*` matches every `user:<id>`.
+fn is_wildcard_for(tuple_user: &str, user: &str) -> bool {
+    match (tuple_user.strip_suffix(":*"), user.split_once(':')) {
+        (Some(wildcard_type), Some((user_type, _))) => wildcard_type == user_type,
+        _ => false,
+    }
+}
+
+fn object_type(object: &str) -> Result<&str, Error> {
+    object
+        .split_once(':')
+        .map(|(object_type, _)| object_type)
+        .ok_or_else(|| validation_error(format!("invalid object '{}'", object)))
+}
+
+fn validation_error(message: String) -> Error {
+    Error::OpenFga {
+        status: 400,
+        code: "validation_error".to_string(),
+        message,
+    }
+}
+
+fn write_error(message: &str, tuple: &RelationshipTuple) -> Error {
+    Error::OpenFga {
+        status: 400,
+        code: "write_failed_due_to_invalid_input".to_string(),
+        message: format!(
+            "{}: user: '{}', relation: '{}', object: '{}'",
+            message, tuple.user, tuple.relation, tuple.object
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::openfga::{make_tuple, TupleKeys};
+    use crate::openfga_memory::*;
+
+    fn example_store() -> InMemoryOpenFga {
+        let model = AuthorizationModel::from_dsl(include_str!("../../example-model.txt")).unwrap();
+        InMemoryOpenFga::new(model).with_tuples([
+            make_tuple("organization:acme", "owner", "repo:api"),
+            make_tuple("user:anne", "owner", "organization:acme"),
+            make_tuple(
+                "organization:acme#member",
+                "repo_reader",
+                "organization:acme",
+            ),
+            make_tuple("user:beth", "member", "organization:acme"),
+            make_tuple("team:core#member", "writer", "repo:api"),
+            make_tuple("team:leads#member", "member", "team:core"),
+            make_tuple("team:core#member", "member", "team:leads"),
+            make_tuple("user:carl", "member", "team:leads"),
+        ])
+    }
+
+    async fn allowed(store: &InMemoryOpenFga, user: &str, relation: &str, object: &str) -> bool {
+        store
+            .check(make_tuple(user, relation, object))
+            .await
+            .unwrap()
+            .allowed
+    }
+
+    #[tokio::test]
+    async fn test_example_model() {
+        let store = example_store();
+        // organization owner -> member -> repo_reader from owner -> reader
+        assert!(allowed(&store, "user:anne", "reader", "repo:api").await);
+        assert!(allowed(&store, "user:beth", "reader", "repo:api").await);
+        assert!(!allowed(&store, "user:beth", "writer", "repo:api").await);
+        // nested, cyclic team membership
+        assert!(allowed(&store, "user:carl", "triager", "repo:api").await);
+        assert!(!allowed(&store, "user:carl", "maintainer", "repo:api").await);
+        assert!(!allowed(&store, "user:dan", "reader", "repo:api").await);
+    }
+
+    #[tokio::test]
+    async fn test_intersection_difference_and_wildcard() {
+        let model = AuthorizationModel::from_dsl(
+            "model
+  schema 1.1
+
+type user
+
+type doc
+  relations
+    define blocked: [user]
+    define editor: [user]
+    define viewer: [user:*] but not blocked
+    define approver: editor and viewer
+",
+        )
+        .unwrap();
+        let store = InMemoryOpenFga::new(model).with_tuples([
+            make_tuple("user:*", "viewer", "doc:1"),
+            make_tuple("user:mallory", "blocked", "doc:1"),
+            make_tuple("user:anne", "editor", "doc:1"),
+            make_tuple("user:mallory", "editor", "doc:1"),
+        ]);
+        assert!(allowed(&store, "user:anne", "viewer", "doc:1").await);
+        assert!(!allowed(&store, "user:mallory", "viewer", "doc:1").await);
+        assert!(allowed(&store, "user:anne", "approver", "doc:1").await);
+        assert!(!allowed(&store, "user:mallory", "approver", "doc:1").await);
+        assert!(!allowed(&store, "user:beth", "approver", "doc:1").await);
+    }
+
+    #[tokio::test]
+    async fn test_tupleset_without_the_relation() {
+        let model = AuthorizationModel::from_dsl(
+            "model
+  schema 1.1
+
+type user
+
+type folder
+  relations
+    define viewer: [user]
+
+type doc
+  relations
+    define parent: [folder, user]
+    define viewer: viewer from parent
+",
+        )
+        .unwrap();
+        let store = InMemoryOpenFga::new(model).with_tuples([
+            make_tuple("user:anne", "parent", "doc:1"),
+            make_tuple("folder:x", "parent", "doc:1"),
+            make_tuple("user:beth", "viewer", "folder:x"),
+        ]);
+        // `user` has no `viewer`, so `user:anne` is skipped instead of failing
+        assert!(!allowed(&store, "user:anne", "viewer", "doc:1").await);
+        assert!(allowed(&store, "user:beth", "viewer", "doc:1").await);
+    }
+
+    #[tokio::test]
+    async fn test_contextual_tuples() {
+        let store = example_store();
+        let res = store
+            .check_with_options(
+                make_tuple("user:dan", "admin", "repo:api"),
+                CheckOptions {
+                    contextual_tuples: vec![make_tuple("user:dan", "admin", "repo:api")],
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert!(res.unwrap().allowed);
+        assert!(!allowed(&store, "user:dan", "admin", "repo:api").await);
+    }
+
+    #[tokio::test]
+    async fn test_write_and_delete() {
+        let store = example_store();
+        let tuple = make_tuple("user:dan", "admin", "repo:api");
+        let write = || {
+            RelationshipAction::Writes(TupleKeys {
+                tuple_keys: vec![tuple.clone()],
+            })
+        };
+
+        store.write_relationship_tuple(write()).await.unwrap();
+        assert!(allowed(&store, "user:dan", "writer", "repo:api").await);
+        let duplicate = store.write_relationship_tuple(write()).await;
+        assert!(matches!(duplicate, Err(Error::OpenFga { status: 400, .. })));
+
+        let delete = RelationshipAction::Deletes(TupleKeys {
+            tuple_keys: vec![tuple],
+        });
+        store.write_relationship_tuple(delete).await.unwrap();
+        assert!(!allowed(&store, "user:dan", "writer", "repo:api").await);
+
+        let unknown = store
+            .write_relationship_tuple(RelationshipAction::Writes(TupleKeys {
+                tuple_keys: vec![make_tuple("user:dan", "admin", "project:1")],
+            }))
+            .await;
+        assert!(matches!(unknown, Err(Error::OpenFga { status: 400, .. })));
+    }
+}
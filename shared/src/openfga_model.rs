@@ -0,0 +1,815 @@
+//! Typed OpenFGA authorization models and a compiler from the DSL used by
+//! `example-model.txt` to the JSON accepted by `write_authorization_model`.
+//!
+//! ```text
+//! model
+//!   schema 1.1
+//!
+//! type repo
+//!   relations
+//!     define owner: [organization]
+//!     define reader: [user, team#member] or triager or repo_reader from owner
+//! ```
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizationModel {
+    pub schema_version: String,
+    pub type_definitions: Vec<TypeDefinition>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TypeDefinition {
+    #[serde(rename = "type")]
+    pub type_name: String,
+    #[serde(default)]
+    pub relations: BTreeMap<String, Userset>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    #[serde(default)]
+    pub relations: BTreeMap<String, RelationMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct RelationMetadata {
+    #[serde(default)]
+    pub directly_related_user_types: Vec<RelationReference>,
+}
+
+/// One entry of a type restriction: `user`, `team#member` or `user:*`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RelationReference {
+    #[serde(rename = "type")]
+    pub type_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wildcard: Option<Wildcard>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Wildcard {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Userset {
+    This(DirectUserset),
+    ComputedUserset(ObjectRelation),
+    TupleToUserset(TupleToUserset),
+    Union(Usersets),
+    Intersection(Usersets),
+    Difference(Difference),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct DirectUserset {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ObjectRelation {
+    #[serde(default)]
+    pub object: String,
+    pub relation: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TupleToUserset {
+    pub tupleset: ObjectRelation,
+    #[serde(rename = "computedUserset")]
+    pub computed_userset: ObjectRelation,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Usersets {
+    pub child: Vec<Userset>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub base: Box<Userset>,
+    pub subtract: Box<Userset>,
+}
+
+impl AuthorizationModel {
+    /// Compiles a model written in the OpenFGA DSL.
+    pub fn from_dsl(input: &str) -> Result<Self, DslError> {
+        let types = Parser::default().parse(input)?;
+        validate(&types)?;
+        Ok(AuthorizationModel {
+            schema_version: SCHEMA_VERSION.to_string(),
+            type_definitions: types.into_iter().map(TypeDef::compile).collect(),
+        })
+    }
+
+    /// Whether `other` describes the same model, ignoring differences in how
+    /// OpenFGA echoes a model back, such as empty metadata.
+    pub fn same_as(&self, other: &AuthorizationModel) -> bool {
+        self.normalized() == other.normalized()
+    }
+
+    fn normalized(&self) -> AuthorizationModel {
+        let mut model = self.clone();
+        for type_definition in &mut model.type_definitions {
+            if matches!(&type_definition.metadata, Some(metadata) if metadata.relations.is_empty())
+            {
+                type_definition.metadata = None;
+            }
+        }
+        model
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("line {line}, column {column}: {message}")]
+pub struct DslError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+const SCHEMA_VERSION: &str = "1.1";
+
+/// Position of a token, 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pos {
+    line: usize,
+    column: usize,
+}
+
+impl Pos {
+    fn error(self, message: impl Into<String>) -> DslError {
+        DslError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tok {
+    Word(String),
+    Symbol(char),
+    End,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    pos: Pos,
+}
+
+fn tokenize(line: &str, line_no: usize) -> Result<Vec<Token>, DslError> {
+    let mut tokens = vec![];
+    let chars: Vec<char> = line.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let pos = Pos {
+            line: line_no,
+            column: i + 1,
+        };
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '#' && (i == 0 || chars[i - 1].is_whitespace()) {
+            // Comment until the end of the line. `team#member` has no space before `#`.
+            break;
+        } else if is_word_char(c) {
+            let start = i;
+            while i < chars.len() && is_word_char(chars[i]) {
+                i += 1;
+            }
+            tokens.push(Token {
+                tok: Tok::Word(chars[start..i].iter().collect()),
+                pos,
+            });
+        } else if ":[],#()*".contains(c) {
+            tokens.push(Token {
+                tok: Tok::Symbol(c),
+                pos,
+            });
+            i += 1;
+        } else {
+            return Err(pos.error(format!("unexpected character '{}'", c)));
+        }
+    }
+    tokens.push(Token {
+        tok: Tok::End,
+        pos: Pos {
+            line: line_no,
+            column: chars.len() + 1,
+        },
+    });
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+#[derive(Debug, Clone)]
+struct Name {
+    value: String,
+    pos: Pos,
+}
+
+#[derive(Debug)]
+struct TypeRef {
+    type_name: Name,
+    relation: Option<Name>,
+    wildcard: bool,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Direct(Vec<TypeRef>),
+    Computed(Name),
+    TupleToUserset { computed: Name, tupleset: Name },
+    Union(Vec<Expr>),
+    Intersection(Vec<Expr>),
+    Difference(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug)]
+struct RelationDef {
+    name: Name,
+    expr: Expr,
+}
+
+#[derive(Debug)]
+struct TypeDef {
+    name: Name,
+    relations: Vec<RelationDef>,
+}
+
+#[derive(Debug, PartialEq, Eq, Default)]
+enum Section {
+    #[default]
+    Start,
+    Model,
+    Schema,
+    Type,
+    Relations,
+}
+
+#[derive(Default)]
+struct Parser {
+    section: Section,
+    types: Vec<TypeDef>,
+}
+
+impl Parser {
+    fn parse(mut self, input: &str) -> Result<Vec<TypeDef>, DslError> {
+        let mut last = Pos { line: 1, column: 1 };
+        for (i, line) in input.lines().enumerate() {
+            let tokens = tokenize(line, i + 1)?;
+            if tokens[0].tok == Tok::End {
+                continue;
+            }
+            last = tokens[0].pos;
+            self.parse_line(Cursor { tokens, i: 0 })?;
+        }
+        match self.section {
+            Section::Start => Err(last.error("expected 'model'")),
+            Section::Model => Err(last.error("expected 'schema 1.1'")),
+            _ => Ok(self.types),
+        }
+    }
+
+    fn parse_line(&mut self, mut cur: Cursor) -> Result<(), DslError> {
+        let keyword = cur.word("a keyword")?;
+        match (keyword.value.as_str(), &self.section) {
+            ("model", Section::Start) => self.section = Section::Model,
+            ("schema", Section::Model) => {
+                let version = cur.word("a schema version")?;
+                if version.value != SCHEMA_VERSION {
+                    return Err(version.pos.error(format!(
+                        "unsupported schema version '{}', expected {}",
+                        version.value, SCHEMA_VERSION
+                    )));
+                }
+                self.section = Section::Schema;
+            }
+            ("type", Section::Schema | Section::Type | Section::Relations) => {
+                let name = cur.word("a type name")?;
+                if self.types.iter().any(|t| t.name.value == name.value) {
+                    return Err(name
+                        .pos
+                        .error(format!("type '{}' is defined more than once", name.value)));
+                }
+                self.types.push(TypeDef {
+                    name,
+                    relations: vec![],
+                });
+                self.section = Section::Type;
+            }
+            ("relations", Section::Type) => self.section = Section::Relations,
+            ("define", Section::Relations) => {
+                let name = cur.word("a relation name")?;
+                cur.symbol(':')?;
+                let expr = parse_relation_expr(&mut cur)?;
+                let current = self.types.last_mut().expect("inside a type");
+                if current.relations.iter().any(|r| r.name.value == name.value) {
+                    return Err(name.pos.error(format!(
+                        "relation '{}' is defined more than once on type '{}'",
+                        name.value, current.name.value
+                    )));
+                }
+                current.relations.push(RelationDef { name, expr });
+            }
+            (other, section) => {
+                let expected = match section {
+                    Section::Start => "'model'",
+                    Section::Model => "'schema'",
+                    Section::Schema => "'type'",
+                    Section::Type => "'relations' or 'type'",
+                    Section::Relations => "'define' or 'type'",
+                };
+                return Err(keyword
+                    .pos
+                    .error(format!("unexpected '{}', expected {}", other, expected)));
+            }
+        }
+        cur.end()
+    }
+}
+
+struct Cursor {
+    tokens: Vec<Token>,
+    i: usize,
+}
+
+impl Cursor {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.i]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.i].clone();
+        if token.tok != Tok::End {
+            self.i += 1;
+        }
+        token
+    }
+
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(&self.peek().tok, Tok::Word(w) if w == word)
+    }
+
+    fn peek_symbol(&self, symbol: char) -> bool {
+        self.peek().tok == Tok::Symbol(symbol)
+    }
+
+    fn word(&mut self, expected: &str) -> Result<Name, DslError> {
+        let token = self.next();
+        match token.tok {
+            Tok::Word(value) => Ok(Name {
+                value,
+                pos: token.pos,
+            }),
+            _ => Err(unexpected(&token, expected)),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), DslError> {
+        let token = self.next();
+        match &token.tok {
+            Tok::Word(w) if w == keyword => Ok(()),
+            _ => Err(unexpected(&token, &format!("'{}'", keyword))),
+        }
+    }
+
+    fn symbol(&mut self, symbol: char) -> Result<(), DslError> {
+        let token = self.next();
+        if token.tok == Tok::Symbol(symbol) {
+            Ok(())
+        } else {
+            Err(unexpected(&token, &format!("'{}'", symbol)))
+        }
+    }
+
+    fn end(&mut self) -> Result<(), DslError> {
+        let token = self.next();
+        if token.tok == Tok::End {
+            Ok(())
+        } else {
+            Err(unexpected(&token, "end of line"))
+        }
+    }
+}
+
+fn unexpected(token: &Token, expected: &str) -> DslError {
+    let found = match &token.tok {
+        Tok::Word(w) => format!("'{}'", w),
+        Tok::Symbol(c) => format!("'{}'", c),
+        Tok::End => "end of line".to_string(),
+    };
+    token
+        .pos
+        .error(format!("expected {}, found {}", expected, found))
+}
+
+/// `grouping ((or grouping)+ | (and grouping)+ | but not grouping)?`
+///
+/// Like the official parser, mixing operators requires parentheses, and type
+/// restrictions may only be the first element of a definition.
+fn parse_relation_expr(cur: &mut Cursor) -> Result<Expr, DslError> {
+    let first = if cur.peek_symbol('[') {
+        parse_direct(cur)?
+    } else {
+        parse_grouping(cur)?
+    };
+    parse_operators(cur, first)
+}
+
+fn parse_operators(cur: &mut Cursor, first: Expr) -> Result<Expr, DslError> {
+    let operator = match &cur.peek().tok {
+        Tok::Word(w) if w == "or" || w == "and" || w == "but" => w.clone(),
+        _ => return Ok(first),
+    };
+    if operator == "but" {
+        cur.keyword("but")?;
+        cur.keyword("not")?;
+        let subtract = parse_grouping(cur)?;
+        if let Tok::Word(w) = &cur.peek().tok {
+            if w == "or" || w == "and" || w == "but" {
+                return Err(cur
+                    .peek()
+                    .pos
+                    .error("use parentheses to combine 'but not' with other operators"));
+            }
+        }
+        return Ok(Expr::Difference(Box::new(first), Box::new(subtract)));
+    }
+
+    let mut children = vec![first];
+    while cur.peek_word(&operator) {
+        cur.next();
+        children.push(parse_grouping(cur)?);
+    }
+    if let Tok::Word(w) = &cur.peek().tok {
+        if w == "or" || w == "and" || w == "but" {
+            return Err(cur.peek().pos.error(format!(
+                "use parentheses to combine '{}' with '{}'",
+                operator, w
+            )));
+        }
+    }
+    Ok(match operator.as_str() {
+        "or" => Expr::Union(children),
+        _ => Expr::Intersection(children),
+    })
+}
+
+fn parse_grouping(cur: &mut Cursor) -> Result<Expr, DslError> {
+    if cur.peek_symbol('[') {
+        return Err(cur
+            .peek()
+            .pos
+            .error("type restrictions must be the first element of a relation definition"));
+    }
+    if cur.peek_symbol('(') {
+        cur.next();
+        let first = parse_grouping(cur)?;
+        let expr = parse_operators(cur, first)?;
+        cur.symbol(')')?;
+        return Ok(expr);
+    }
+    let computed = cur.word("a relation name")?;
+    if cur.peek_word("from") {
+        cur.next();
+        let tupleset = cur.word("a relation name")?;
+        return Ok(Expr::TupleToUserset { computed, tupleset });
+    }
+    Ok(Expr::Computed(computed))
+}
+
+fn parse_direct(cur: &mut Cursor) -> Result<Expr, DslError> {
+    cur.symbol('[')?;
+    let mut refs = vec![];
+    loop {
+        let type_name = cur.word("a type name")?;
+        let mut type_ref = TypeRef {
+            type_name,
+            relation: None,
+            wildcard: false,
+        };
+        if cur.peek_symbol('#') {
+            cur.next();
+            type_ref.relation = Some(cur.word("a relation name")?);
+        } else if cur.peek_symbol(':') {
+            cur.next();
+            cur.symbol('*')?;
+            type_ref.wildcard = true;
+        }
+        if cur.peek_word("with") {
+            return Err(cur.peek().pos.error("conditions are not supported"));
+        }
+        refs.push(type_ref);
+        if cur.peek_symbol(',') {
+            cur.next();
+            continue;
+        }
+        cur.symbol(']')?;
+        return Ok(Expr::Direct(refs));
+    }
+}
+
+/// Checks that every referenced type and relation exists.
+fn validate(types: &[TypeDef]) -> Result<(), DslError> {
+    let relations: HashMap<&str, Vec<&str>> = types
+        .iter()
+        .map(|t| {
+            (
+                t.name.value.as_str(),
+                t.relations.iter().map(|r| r.name.value.as_str()).collect(),
+            )
+        })
+        .collect();
+    let has_relation = |type_name: &str, relation: &str| {
+        relations
+            .get(type_name)
+            .map(|r| r.contains(&relation))
+            .unwrap_or(false)
+    };
+
+    for type_def in types {
+        let type_name = type_def.name.value.as_str();
+        for relation in &type_def.relations {
+            let mut exprs = vec![&relation.expr];
+            while let Some(expr) = exprs.pop() {
+                match expr {
+                    Expr::Direct(refs) => {
+                        for r in refs {
+                            if !relations.contains_key(r.type_name.value.as_str()) {
+                                return Err(r
+                                    .type_name
+                                    .pos
+                                    .error(format!("unknown type '{}'", r.type_name.value)));
+                            }
+                            if let Some(rel) = &r.relation {
+                                if !has_relation(&r.type_name.value, &rel.value) {
+                                    return Err(rel.pos.error(format!(
+                                        "type '{}' has no relation '{}'",
+                                        r.type_name.value, rel.value
+                                    )));
+                                }
+                            }
+                        }
+                    }
+                    Expr::Computed(name) => {
+                        if !has_relation(type_name, &name.value) {
+                            return Err(name.pos.error(format!(
+                                "type '{}' has no relation '{}'",
+                                type_name, name.value
+                            )));
+                        }
+                    }
+                    Expr::TupleToUserset { tupleset, .. } => {
+                        // The computed relation is looked up on the types the
+                        // tupleset points at, which the server validates.
+                        if !has_relation(type_name, &tupleset.value) {
+                            return Err(tupleset.pos.error(format!(
+                                "type '{}' has no relation '{}'",
+                                type_name, tupleset.value
+                            )));
+                        }
+                    }
+                    Expr::Union(children) | Expr::Intersection(children) => {
+                        exprs.extend(children.iter())
+                    }
+                    Expr::Difference(base, subtract) => {
+                        exprs.push(base);
+                        exprs.push(subtract);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl TypeDef {
+    fn compile(self) -> TypeDefinition {
+        let mut relations = BTreeMap::new();
+        let mut metadata = BTreeMap::new();
+        for relation in self.relations {
+            let mut direct = vec![];
+            let userset = compile_expr(relation.expr, &mut direct);
+            relations.insert(relation.name.value.clone(), userset);
+            metadata.insert(
+                relation.name.value,
+                RelationMetadata {
+                    directly_related_user_types: direct,
+                },
+            );
+        }
+        TypeDefinition {
+            type_name: self.name.value,
+            metadata: (!relations.is_empty()).then_some(Metadata {
+                relations: metadata,
+            }),
+            relations,
+        }
+    }
+}
+
+fn compile_expr(expr: Expr, direct: &mut Vec<RelationReference>) -> Userset {
+    match expr {
+        Expr::Direct(refs) => {
+            direct.extend(refs.into_iter().map(|r| RelationReference {
+                type_name: r.type_name.value,
+                relation: r.relation.map(|n| n.value),
+                wildcard: r.wildcard.then_some(Wildcard {}),
+            }));
+            Userset::This(DirectUserset {})
+        }
+        Expr::Computed(name) => Userset::ComputedUserset(ObjectRelation {
+            object: String::new(),
+            relation: name.value,
+        }),
+        Expr::TupleToUserset { computed, tupleset } => Userset::TupleToUserset(TupleToUserset {
+            tupleset: ObjectRelation {
+                object: String::new(),
+                relation: tupleset.value,
+            },
+            computed_userset: ObjectRelation {
+                object: String::new(),
+                relation: computed.value,
+            },
+        }),
+        Expr::Union(children) => Userset::Union(Usersets {
+            child: children
+                .into_iter()
+                .map(|c| compile_expr(c, direct))
+                .collect(),
+        }),
+        Expr::Intersection(children) => Userset::Intersection(Usersets {
+            child: children
+                .into_iter()
+                .map(|c| compile_expr(c, direct))
+                .collect(),
+        }),
+        Expr::Difference(base, subtract) => Userset::Difference(Difference {
+            base: Box::new(compile_expr(*base, direct)),
+            subtract: Box::new(compile_expr(*subtract, direct)),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::openfga_model::*;
+    use serde_json::json;
+
+    fn computed(relation: &str) -> Userset {
+        Userset::ComputedUserset(ObjectRelation {
+            object: String::new(),
+            relation: relation.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_simple_model_matches_json() {
+        let model = AuthorizationModel::from_dsl(include_str!("../../simple-model.txt")).unwrap();
+        let expected: AuthorizationModel = serde_json::from_str(
+            r#"{"schema_version":"1.1","type_definitions":[{"type":"user"},{"type":"document","relations":{"reader":{"this":{}},"writer":{"this":{}},"owner":{"this":{}}},"metadata":{"relations":{"reader":{"directly_related_user_types":[{"type":"user"}]},"writer":{"directly_related_user_types":[{"type":"user"}]},"owner":{"directly_related_user_types":[{"type":"user"}]}}}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(model, expected);
+    }
+
+    #[test]
+    fn test_example_model() {
+        let model = AuthorizationModel::from_dsl(include_str!("../../example-model.txt")).unwrap();
+        let types: Vec<&str> = model
+            .type_definitions
+            .iter()
+            .map(|t| t.type_name.as_str())
+            .collect();
+        assert_eq!(types, vec!["organization", "repo", "team", "user"]);
+
+        let repo = &model.type_definitions[1];
+        assert_eq!(
+            repo.relations["reader"],
+            Userset::Union(Usersets {
+                child: vec![
+                    Userset::This(DirectUserset {}),
+                    computed("triager"),
+                    Userset::TupleToUserset(TupleToUserset {
+                        tupleset: ObjectRelation {
+                            object: String::new(),
+                            relation: "owner".to_string(),
+                        },
+                        computed_userset: ObjectRelation {
+                            object: String::new(),
+                            relation: "repo_reader".to_string(),
+                        },
+                    }),
+                ]
+            })
+        );
+        let metadata = repo.metadata.as_ref().unwrap();
+        assert_eq!(
+            serde_json::to_value(&metadata.relations["reader"]).unwrap(),
+            json!({"directly_related_user_types": [
+                {"type": "user"},
+                {"type": "team", "relation": "member"}
+            ]})
+        );
+        assert_eq!(
+            serde_json::to_value(&metadata.relations["owner"]).unwrap(),
+            json!({"directly_related_user_types": [{"type": "organization"}]})
+        );
+    }
+
+    #[test]
+    fn test_and_but_not_and_wildcard() {
+        let model = AuthorizationModel::from_dsl(
+            "model
+  schema 1.1
+type user
+type doc
+  relations
+    define blocked: [user]
+    define editor: [user]
+    define viewer: [user:*] but not blocked # public unless blocked
+    define admin: (editor and viewer) or blocked
+",
+        )
+        .unwrap();
+        let doc = &model.type_definitions[1];
+        assert_eq!(
+            doc.relations["viewer"],
+            Userset::Difference(Difference {
+                base: Box::new(Userset::This(DirectUserset {})),
+                subtract: Box::new(computed("blocked")),
+            })
+        );
+        assert_eq!(
+            doc.relations["admin"],
+            Userset::Union(Usersets {
+                child: vec![
+                    Userset::Intersection(Usersets {
+                        child: vec![computed("editor"), computed("viewer")]
+                    }),
+                    computed("blocked"),
+                ]
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(&doc.metadata.as_ref().unwrap().relations["viewer"]).unwrap(),
+            json!({"directly_related_user_types": [{"type": "user", "wildcard": {}}]})
+        );
+    }
+
+    #[test]
+    fn test_errors_have_positions() {
+        let err = |dsl: &str| AuthorizationModel::from_dsl(dsl).unwrap_err();
+
+        assert_eq!(
+            err("model\n  schema 1.1\ntype doc\n  relations\n    define viewer: [user]\n"),
+            DslError {
+                line: 5,
+                column: 21,
+                message: "unknown type 'user'".to_string(),
+            }
+        );
+        assert_eq!(
+            err("model\n  schema 1.1\ntype user\ntype doc\n  relations\n    define viewer: [user] or editor\n"),
+            DslError {
+                line: 6,
+                column: 30,
+                message: "type 'doc' has no relation 'editor'".to_string(),
+            }
+        );
+        assert_eq!(
+            err("model\n  schema 1.1\ntype user\ntype doc\n  relations\n    define a: [user]\n    define b: a or a and a\n"),
+            DslError {
+                line: 7,
+                column: 22,
+                message: "use parentheses to combine 'or' with 'and'".to_string(),
+            }
+        );
+        assert_eq!(
+            err("model\n  schema 2.0\n"),
+            DslError {
+                line: 2,
+                column: 10,
+                message: "unsupported schema version '2.0', expected 1.1".to_string(),
+            }
+        );
+        assert_eq!(
+            err("model\n  schema 1.1\ntype doc\n    define a: [doc]\n"),
+            DslError {
+                line: 4,
+                column: 5,
+                message: "unexpected 'define', expected 'relations' or 'type'".to_string(),
+            }
+        );
+    }
+}
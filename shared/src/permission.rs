@@ -0,0 +1,52 @@
+//! Roles that can be granted to users and the website permissions they give.
+//! Every logged in user has [`USER`]'s permissions; roles add to them.
+
+pub const CATEGORY_VIEW: &str = "Category::View";
+pub const ADMIN_VIEW: &str = "Admin::View";
+
+pub const ADMIN: &str = "admin";
+
+/// Every role that can be granted.
+pub const ROLES: &[&str] = &[ADMIN];
+
+/// Permissions of visitors who are not logged in.
+pub const GUEST: &[&str] = &[CATEGORY_VIEW];
+/// Permissions of every logged in user.
+pub const USER: &[&str] = &[CATEGORY_VIEW];
+
+pub fn is_known_role(role: &str) -> bool {
+    ROLES.contains(&role)
+}
+
+fn role_permissions(role: &str) -> &'static [&'static str] {
+    match role {
+        ADMIN => &[ADMIN_VIEW],
+        _ => &[],
+    }
+}
+
+/// The permissions of a logged in user with `roles`, sorted.
+pub fn for_roles(roles: &[String]) -> Vec<String> {
+    let mut permissions: Vec<String> = USER
+        .iter()
+        .chain(roles.iter().flat_map(|role| role_permissions(role)))
+        .map(|permission| permission.to_string())
+        .collect();
+    permissions.sort();
+    permissions.dedup();
+    permissions
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::permission::*;
+
+    #[test]
+    fn test_for_roles() {
+        assert_eq!(for_roles(&[]), vec![CATEGORY_VIEW]);
+        assert_eq!(
+            for_roles(&[ADMIN.to_string(), "retired".to_string()]),
+            vec![ADMIN_VIEW, CATEGORY_VIEW]
+        );
+    }
+}
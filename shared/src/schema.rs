@@ -23,6 +23,12 @@ pub struct PathName {
     pub name: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct PathUserRole {
+    pub id: Uuid,
+    pub role: String,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct CreateAccount {
     pub name: String,
@@ -42,13 +48,87 @@ pub struct LoginPayload2 {
     pub password: String,
 }
 
+/// An identity verified by an OpenID Connect provider.
+#[derive(Deserialize, Debug, Serialize)]
+pub struct LinkIdentity {
+    /// Name of the provider in the website's configuration.
+    pub provider: String,
+    /// The ID token's `sub`, unique per provider.
+    pub subject: String,
+    pub email: Option<String>,
+    /// Name for the user if one is created.
+    pub name: Option<String>,
+    /// Links a new identity to this user, e.g. one logged in with a
+    /// password, instead of creating a user.
+    pub user_id: Option<Uuid>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct CreateClient {
     pub name: String,
-    pub user_id: Uuid,
+    /// Only services may create clients for other users; everyone else gets
+    /// a client of their own.
+    pub user_id: Option<Uuid>,
+    /// Defaults to the scopes in `scope::DEFAULT`.
+    pub scopes: Option<Vec<String>>,
+    /// Defaults to a token that never expires.
+    pub expires_in_seconds: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
+pub struct RotateClient {
+    /// Defaults to a day, at most 30 days.
+    pub grace_period_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct ValidateToken {
     pub token: String,
 }
+
+impl std::fmt::Debug for ValidateToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ValidateToken")
+            .field("token", &"[REDACTED]")
+            .finish()
+    }
+}
+
+/// Shortest password accepted when setting a new one.
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangePassword {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+impl std::fmt::Debug for ChangePassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChangePassword")
+            .field("current_password", &"[REDACTED]")
+            .field("new_password", &"[REDACTED]")
+            .finish()
+    }
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct RequestPasswordReset {
+    pub name: String,
+}
+
+/// Sets a new password with a token from a password reset notification.
+#[derive(Serialize, Deserialize)]
+pub struct ResetPassword {
+    pub token: String,
+    pub new_password: String,
+}
+
+impl std::fmt::Debug for ResetPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResetPassword")
+            .field("token", &"[REDACTED]")
+            .field("new_password", &"[REDACTED]")
+            .finish()
+    }
+}
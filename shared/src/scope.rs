@@ -0,0 +1,27 @@
+//! Scopes a client token can be limited to. A token may only call the
+//! routes covered by its scopes.
+use crate::auth::ScopeRules;
+
+pub const ACCOUNTS_READ: &str = "accounts:read";
+pub const ACCOUNTS_WRITE: &str = "accounts:write";
+pub const CLIENTS_WRITE: &str = "clients:write";
+pub const ROLES_WRITE: &str = "roles:write";
+
+/// Every known scope.
+pub const ALL: &[&str] = &[ACCOUNTS_READ, ACCOUNTS_WRITE, CLIENTS_WRITE, ROLES_WRITE];
+
+/// Granted when a client is created without explicit scopes. Managing roles
+/// must be asked for explicitly, and only admins may.
+pub const DEFAULT: &[&str] = &[ACCOUNTS_READ, ACCOUNTS_WRITE, CLIENTS_WRITE];
+
+pub fn is_known(scope: &str) -> bool {
+    ALL.contains(&scope)
+}
+
+/// The scopes each api-server path needs, shared by the api-server router
+/// and the website proxy in front of it.
+pub fn rules() -> ScopeRules {
+    ScopeRules::new()
+        .rule("/api/accounts", Some(ACCOUNTS_READ), Some(ACCOUNTS_WRITE))
+        .rule("/api/clients", None, Some(CLIENTS_WRITE))
+}
@@ -0,0 +1,181 @@
+use crate::model::ClientModel;
+use moka::sync::Cache;
+use opentelemetry::{global, metrics::Counter, KeyValue};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// SHA-256 of a token, so raw tokens never sit in memory longer than a request.
+type TokenKey = [u8; 32];
+
+#[derive(Debug, Clone)]
+pub enum CachedToken {
+    Valid(ClientModel),
+    Invalid,
+}
+
+/// TTL and size bounded cache of client token validations done by
+/// `token_auth`.
+///
+/// Rejected tokens are remembered for a shorter time than accepted ones.
+/// Revocations and rotations seen by this process evict the client with
+/// [`TokenCache::evict_client`]; ones made elsewhere take up to the TTL to
+/// apply, so keep it short. Expiry (`expires_at`) is checked on every use
+/// and needs no eviction.
+#[derive(Debug)]
+pub struct TokenCache {
+    valid: Cache<TokenKey, ClientModel>,
+    invalid: Cache<TokenKey, ()>,
+    requests: OnceLock<Counter<u64>>,
+}
+
+impl TokenCache {
+    pub fn new(ttl: Duration, negative_ttl: Duration, max_entries: u64) -> Self {
+        Self {
+            valid: Cache::builder()
+                .time_to_live(ttl)
+                .max_capacity(max_entries)
+                .support_invalidation_closures()
+                .build(),
+            invalid: Cache::builder()
+                .time_to_live(negative_ttl)
+                .max_capacity(max_entries)
+                .build(),
+            requests: OnceLock::new(),
+        }
+    }
+
+    /// Reads `TOKEN_CACHE_TTL_SECONDS` (default 30),
+    /// `TOKEN_CACHE_NEGATIVE_TTL_SECONDS` (default 5) and
+    /// `TOKEN_CACHE_MAX_ENTRIES` (default 10000).
+    pub fn from_env() -> Self {
+        let ttl = env_u64("TOKEN_CACHE_TTL_SECONDS", 30);
+        let negative_ttl = env_u64("TOKEN_CACHE_NEGATIVE_TTL_SECONDS", 5);
+        let max_entries = env_u64("TOKEN_CACHE_MAX_ENTRIES", 10_000);
+        Self::new(
+            Duration::from_secs(ttl),
+            Duration::from_secs(negative_ttl),
+            max_entries,
+        )
+    }
+
+    pub fn get(&self, token: &str) -> Option<CachedToken> {
+        let key = key(token);
+        let cached = match self.valid.get(&key) {
+            Some(client) => Some(CachedToken::Valid(client)),
+            None => self.invalid.get(&key).map(|_| CachedToken::Invalid),
+        };
+        let result = match cached {
+            Some(CachedToken::Valid(_)) => "hit",
+            Some(CachedToken::Invalid) => "negative_hit",
+            None => "miss",
+        };
+        self.requests().add(1, &[KeyValue::new("result", result)]);
+        cached
+    }
+
+    pub fn insert_valid(&self, token: &str, client: ClientModel) {
+        self.valid.insert(key(token), client);
+    }
+
+    pub fn insert_invalid(&self, token: &str) {
+        self.invalid.insert(key(token), ());
+    }
+
+    /// Forgets every token of `client_id`, e.g. after it was revoked or its
+    /// token rotated.
+    pub fn evict_client(&self, client_id: Uuid) {
+        self.valid
+            .invalidate_entries_if(move |_, client| client.id == client_id)
+            .expect("invalidation closures are enabled");
+    }
+
+    pub fn evict_token(&self, token: &str) {
+        let key = key(token);
+        self.valid.invalidate(&key);
+        self.invalid.invalidate(&key);
+    }
+
+    // Created on first use, see `CheckCache::requests`.
+    fn requests(&self) -> &Counter<u64> {
+        self.requests.get_or_init(|| {
+            global::meter("auth")
+                .u64_counter("client_token.cache.requests")
+                .with_description(
+                    "Client token cache lookups by result (hit, negative_hit or miss)",
+                )
+                .init()
+        })
+    }
+}
+
+fn key(token: &str) -> TokenKey {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .map(|v| {
+            v.parse::<u64>()
+                .unwrap_or_else(|_| panic!("{} must be a number", name))
+        })
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::token_cache::*;
+
+    fn client() -> ClientModel {
+        ClientModel {
+            id: Uuid::new_v4(),
+            name: "ci".to_string(),
+            user_id: Uuid::new_v4(),
+            scopes: vec![],
+            expires_at: None,
+            revoked_at: None,
+            last_used_at: None,
+        }
+    }
+
+    fn cache() -> TokenCache {
+        TokenCache::new(Duration::from_secs(60), Duration::from_secs(60), 100)
+    }
+
+    #[test]
+    fn test_valid_and_invalid_tokens() {
+        let cache = cache();
+        let client = client();
+        assert!(cache.get("rtc_a_secret").is_none());
+
+        cache.insert_valid("rtc_a_secret", client.clone());
+        cache.insert_invalid("rtc_b_guess");
+        assert!(matches!(
+            cache.get("rtc_a_secret"),
+            Some(CachedToken::Valid(cached)) if cached.id == client.id
+        ));
+        assert!(matches!(
+            cache.get("rtc_b_guess"),
+            Some(CachedToken::Invalid)
+        ));
+
+        cache.evict_token("rtc_b_guess");
+        assert!(cache.get("rtc_b_guess").is_none());
+    }
+
+    #[test]
+    fn test_evict_client() {
+        let cache = cache();
+        let revoked = client();
+        let other = client();
+        cache.insert_valid("rtc_old_secret", revoked.clone());
+        cache.insert_valid("rtc_new_secret", revoked.clone());
+        cache.insert_valid("rtc_other_secret", other);
+
+        cache.evict_client(revoked.id);
+        assert!(cache.get("rtc_old_secret").is_none());
+        assert!(cache.get("rtc_new_secret").is_none());
+        assert!(cache.get("rtc_other_secret").is_some());
+    }
+}
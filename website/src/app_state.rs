@@ -1,4 +1,4 @@
-use crate::auth0;
+use crate::oidc;
 use fluent_templates::{ArcLoader, FluentLoader};
 use handlebars::Handlebars;
 use std::sync::Arc;
@@ -6,7 +6,7 @@ use std::sync::Arc;
 #[derive(Debug)]
 pub struct AppState {
     pub handlebars: Handlebars<'static>,
-    pub auth0: auth0::AuthSettings,
+    pub oidc: oidc::Providers,
 }
 
 pub async fn create_app_state() -> Arc<AppState> {
@@ -17,11 +17,11 @@ pub async fn create_app_state() -> Arc<AppState> {
         .unwrap();
 
     let mut handlebars = handlebars::Handlebars::new();
-    let auth0 = auth0::AuthSettings::from_env();
+    let oidc = oidc::Providers::from_env();
     handlebars.register_helper("fluent", Box::new(FluentLoader::new(arc)));
     handlebars
         .register_templates_directory(".hbs", "handlebars/")
         .unwrap(); // TODO better error handling
-    let app_state = Arc::new(AppState { handlebars, auth0 });
+    let app_state = Arc::new(AppState { handlebars, oidc });
     app_state
 }
@@ -1,30 +1,63 @@
-use axum::{async_trait, http::Request, http::StatusCode, middleware::Next, response::Response};
-use axum_session::{SessionConfig, SessionLayer, SessionNullPool, SessionStore};
+use crate::sessions::{SessionPool, SessionType};
+use axum::{
+    async_trait,
+    extract::State,
+    http::Request,
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_session::{SessionLayer, SessionStore};
 use axum_session_auth::{AuthConfig, AuthSession, AuthSessionLayer, Authentication, HasPermission};
 use serde::{Deserialize, Serialize};
-use shared::client;
+use shared::{authz::Principal, client, permission};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{collections::HashSet, sync::Arc};
 use uuid::Uuid;
 
+/// Session key of the [`SessionUserAuthData`].
+pub const USER_AUTH_DATA_KEY: &str = "user_auth_data";
+/// Session key of when the user last made a request, in seconds since the epoch.
+const LAST_SEEN_KEY: &str = "last_seen";
+// Saves rewriting the session on every request.
+const LAST_SEEN_RESOLUTION_SECONDS: u64 = 60;
+
+/// Data to be stored in the session after an OpenID Connect login.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionUserAuthData {
+    /// Name of the provider the user logged in with.
+    pub provider: String,
+    pub sub: String,
+    /// The ID token's expiry, which ends the session.
+    pub exp: i64,
+    pub raw_jwt: String,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is after 1970")
+        .as_secs()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: Uuid,
     pub anonymous: bool,
     pub username: String,
+    pub roles: Vec<String>,
     pub permissions: HashSet<String>,
 }
 
 impl Default for User {
+    /// The guest, whose id is nil as it isn't an api-server user.
     fn default() -> Self {
-        let mut permissions = HashSet::new();
-
-        permissions.insert("Category::View".to_owned());
-
         Self {
-            id: Uuid::parse_str("b416d7eb-7c5b-4133-96a4-49c860cea964").unwrap(),
+            id: Uuid::nil(),
             anonymous: true,
             username: "Guest".into(),
-            permissions,
+            roles: Vec::new(),
+            permissions: permission::GUEST.iter().map(|p| p.to_string()).collect(),
         }
     }
 }
@@ -32,8 +65,8 @@ impl Default for User {
 // We place our Type within a Arc<> so we can send it across async threads.
 pub type NullPool = Arc<Option<()>>;
 pub type AuthIdType = Option<Uuid>;
-pub type AuthSessionType = AuthSession<User, AuthIdType, SessionNullPool, NullPool>;
-type AuthSessionLayerType = AuthSessionLayer<User, AuthIdType, SessionNullPool, NullPool>;
+pub type AuthSessionType = AuthSession<User, AuthIdType, SessionPool, NullPool>;
+type AuthSessionLayerType = AuthSessionLayer<User, AuthIdType, SessionPool, NullPool>;
 
 #[async_trait]
 impl Authentication<User, Option<Uuid>, NullPool> for User {
@@ -55,16 +88,21 @@ impl Authentication<User, Option<Uuid>, NullPool> for User {
             None => return Ok(User::default()),
         };
         tracing::info!("found user {}", user.id);
-        let mut permissions = HashSet::new();
-
-        permissions.insert("Category::View".to_owned());
-        permissions.insert("Admin::View".to_owned());
+        // without its roles the user still gets the permissions everyone has
+        let roles = match client::get_user_permissions(user.id, None).await {
+            Ok(permissions) => permissions.roles,
+            Err(e) => {
+                tracing::error!("Failed to get permissions of user {}: {}", user.id, e);
+                Vec::new()
+            }
+        };
 
         Ok(User {
             id: user.id,
             anonymous: false,
             username: user.name,
-            permissions,
+            permissions: permission::for_roles(&roles).into_iter().collect(),
+            roles,
         })
     }
 
@@ -89,26 +127,101 @@ impl HasPermission<NullPool> for User {
 }
 
 pub async fn session_auth<B>(
-    auth: AuthSession<User, Option<Uuid>, SessionNullPool, NullPool>,
+    auth: AuthSession<User, Option<Uuid>, SessionPool, NullPool>,
     // you can also add more extractors here but the last
     // extractor must implement `FromRequest` which
     // `Request` does
-    request: Request<B>,
+    mut request: Request<B>,
     next: Next<B>,
 ) -> Result<Response, StatusCode> {
-    if auth.is_anonymous() {
-        Err(StatusCode::UNAUTHORIZED)
-    } else {
-        let response = next.run(request).await;
-        Ok(response)
+    match auth.current_user {
+        Some(user) if !user.anonymous => {
+            // lets `shared::authz::require_relation` authorize the session user
+            request.extensions_mut().insert(Principal::user(user.id));
+            let response = next.run(request).await;
+            Ok(response)
+        }
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SessionTimeouts {
+    /// Logs users out after this long without a request.
+    pub idle: Duration,
+}
+
+impl SessionTimeouts {
+    /// Reads `SESSION_IDLE_TIMEOUT_SECONDS` (default 1800).
+    pub fn from_env() -> Self {
+        let idle = std::env::var("SESSION_IDLE_TIMEOUT_SECONDS")
+            .map(|v| {
+                v.parse()
+                    .expect("SESSION_IDLE_TIMEOUT_SECONDS must be a number")
+            })
+            .unwrap_or(1800);
+        Self {
+            idle: Duration::from_secs(idle),
+        }
+    }
+}
+
+/// Logs the user in under a new session ID, so an ID planted in the browser
+/// before the login (session fixation) doesn't end up logged in. `login_user`
+/// renews it too in axum_session_auth 0.7, this doesn't rely on that.
+pub fn start_session(auth: &AuthSessionType, session: &SessionType, user_id: Uuid) {
+    session.renew();
+    auth.login_user(Some(user_id));
+}
+
+/// Logs the user out and forgets everything in their session.
+pub fn end_session(auth: &AuthSessionType, session: &SessionType) {
+    auth.logout_user();
+    session.destroy();
+}
+
+/// Ends sessions whose ID token expired or that were idle for too long,
+/// sending the user back to the login page.
+pub async fn session_expiry<B>(
+    State(timeouts): State<SessionTimeouts>,
+    session: SessionType,
+    auth: AuthSessionType,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(user) = auth.current_user.as_ref().filter(|user| !user.anonymous) else {
+        return next.run(request).await;
+    };
+    let now = unix_now();
+    let token_expired = session
+        .get::<SessionUserAuthData>(USER_AUTH_DATA_KEY)
+        .is_some_and(|data| data.exp <= now as i64);
+    let last_seen = session.get::<u64>(LAST_SEEN_KEY);
+    let idle =
+        last_seen.is_some_and(|last_seen| now.saturating_sub(last_seen) > timeouts.idle.as_secs());
+    if token_expired || idle {
+        tracing::info!(
+            "Session of user {} expired (token expired: {}, idle: {})",
+            user.id,
+            token_expired,
+            idle
+        );
+        end_session(&auth, &session);
+        return Redirect::to("/login").into_response();
+    }
+    let stale = match last_seen {
+        Some(last_seen) => now.saturating_sub(last_seen) >= LAST_SEEN_RESOLUTION_SECONDS,
+        None => true,
+    };
+    if stale {
+        session.set(LAST_SEEN_KEY, now);
     }
+    next.run(request).await
 }
 
-pub async fn make_auth_session_layer() -> (AuthSessionLayerType, SessionLayer<SessionNullPool>) {
-    let session_config = SessionConfig::default().with_table_name("sessions_table");
-    let session_store = SessionStore::<SessionNullPool>::new(None, session_config)
-        .await
-        .unwrap();
+pub fn make_auth_session_layer(
+    session_store: SessionStore<SessionPool>,
+) -> (AuthSessionLayerType, SessionLayer<SessionPool>) {
     let auth_config = AuthConfig::<Option<Uuid>>::default();
     let nullpool = Arc::new(Option::None);
     let layer = AuthSessionLayerType::new(Some(nullpool)).with_config(auth_config);
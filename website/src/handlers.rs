@@ -1,6 +1,7 @@
 use crate::{
     app_state::AppState,
-    auth::{AuthSessionType, NullPool, User},
+    auth::{self, AuthSessionType, NullPool, SessionUserAuthData, User, USER_AUTH_DATA_KEY},
+    sessions::SessionType,
 };
 
 use askama::Template;
@@ -12,19 +13,42 @@ use axum::{
 };
 use axum_session_auth::{Auth, Rights};
 use reqwest_middleware::ClientWithMiddleware;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use serde_json::json;
-use shared::{client, schema::LoginPayload2};
+use shared::{
+    client,
+    client_ip::ClientIp,
+    error::Error,
+    permission,
+    schema::{
+        ChangePassword, LoginPayload2, RequestPasswordReset, ResetPassword, MIN_PASSWORD_LENGTH,
+    },
+};
 use std::{collections::HashMap, sync::Arc};
 use uuid::Uuid;
 
+/// A configured OpenID Connect provider, as linked from the login page.
+struct LoginProvider {
+    name: String,
+    display_name: String,
+}
+
 #[derive(Template)]
 #[template(path = "login.html")]
-struct LoginTemplate {}
+struct LoginTemplate {
+    providers: Vec<LoginProvider>,
+}
 
-pub async fn login() -> impl IntoResponse {
-    let template = LoginTemplate {};
-    template
+pub async fn login(State(data): State<Arc<AppState>>) -> impl IntoResponse {
+    let providers = data
+        .oidc
+        .iter()
+        .map(|provider| LoginProvider {
+            name: provider.config.name.clone(),
+            display_name: provider.config.display_name.clone(),
+        })
+        .collect();
+    LoginTemplate { providers }
 }
 
 pub async fn about_page(State(data): State<Arc<AppState>>) -> Html<String> {
@@ -61,22 +85,9 @@ pub async fn styles() -> impl IntoResponse {
     template
 }
 
-// TODO hacky copy
-/// Data to be stored in the session.
-#[derive(Debug, Serialize, Deserialize)]
-struct SessionUserAuthData {
-    sub: String,
-    exp: i64,
-    raw_jwt: String,
-}
-
-pub async fn loggedin(
-    session: axum_session::Session<axum_session::SessionNullPool>,
-    State(data): State<Arc<AppState>>,
-) -> Html<String> {
-    let x: Option<SessionUserAuthData> = session.get("user_auth_data");
-    println!("user auth data");
-    println!("{:?}", x);
+pub async fn loggedin(session: SessionType, State(data): State<Arc<AppState>>) -> Html<String> {
+    let x: Option<SessionUserAuthData> = session.get(USER_AUTH_DATA_KEY);
+    tracing::debug!("logged in as {:?}", x.map(|data| data.sub));
     let data0 = json!({
         "lang": "de-DE",
     });
@@ -93,17 +104,26 @@ pub struct Input {
 #[debug_handler]
 pub async fn handle_login(
     auth: AuthSessionType,
+    session: SessionType,
+    client_ip: ClientIp,
     extract::Form(input): extract::Form<Input>,
 ) -> Redirect {
     let login_payload = LoginPayload2 {
         name: input.name,
         password: input.password,
     };
-    match client::auth_user(login_payload, None).await {
+    // the api-server throttles logins per client IP
+    match client::auth_user(login_payload, Some(client_ip.to_headers())).await {
         Ok(user) => {
-            auth.login_user(Some(user.id));
+            auth::start_session(&auth, &session, user.id);
             Redirect::to("/perm")
         }
+        Err(Error::TooManyRequests {
+            retry_after_seconds,
+        }) => {
+            tracing::info!("Login throttled for {} seconds", retry_after_seconds);
+            Redirect::to("/login")
+        }
         Err(e) => {
             tracing::error!("TODO REMOVE error {:?}", e);
             Redirect::to("/login")
@@ -111,14 +131,169 @@ pub async fn handle_login(
     }
 }
 
+#[derive(Template)]
+#[template(path = "password_change.html")]
+struct PasswordChangeTemplate {
+    message: Option<&'static str>,
+    min_length: usize,
+}
+
+pub async fn password_change_form() -> impl IntoResponse {
+    PasswordChangeTemplate {
+        message: None,
+        min_length: MIN_PASSWORD_LENGTH,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PasswordChangeInput {
+    current_password: String,
+    new_password: String,
+    confirm_password: String,
+}
+
+pub async fn handle_password_change(
+    auth: AuthSessionType,
+    client_ip: ClientIp,
+    extract::Form(input): extract::Form<PasswordChangeInput>,
+) -> impl IntoResponse {
+    let current_user = auth.current_user.clone().unwrap_or_default();
+    let message = if input.new_password != input.confirm_password {
+        "The new passwords don't match."
+    } else {
+        let payload = ChangePassword {
+            current_password: input.current_password,
+            new_password: input.new_password,
+        };
+        match client::change_password(current_user.id, payload, Some(client_ip.to_headers())).await
+        {
+            Ok(()) => "Your password was changed.",
+            Err(Error::BadRequest) => "The new password is too short.",
+            Err(Error::Unauthorized) => "The current password is wrong.",
+            Err(Error::TooManyRequests { .. }) => "Too many attempts, please try again later.",
+            Err(e) => {
+                tracing::error!("Failed to change the password: {:?}", e);
+                "Your password could not be changed, please try again later."
+            }
+        }
+    };
+    PasswordChangeTemplate {
+        message: Some(message),
+        min_length: MIN_PASSWORD_LENGTH,
+    }
+}
+
+#[derive(Template)]
+#[template(path = "password_forgot.html")]
+struct PasswordForgotTemplate {
+    message: Option<&'static str>,
+}
+
+pub async fn password_forgot_form() -> impl IntoResponse {
+    PasswordForgotTemplate { message: None }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PasswordForgotInput {
+    name: String,
+}
+
+pub async fn handle_password_forgot(
+    client_ip: ClientIp,
+    extract::Form(input): extract::Form<PasswordForgotInput>,
+) -> impl IntoResponse {
+    let payload = RequestPasswordReset { name: input.name };
+    let message = match client::request_password_reset(payload, Some(client_ip.to_headers())).await
+    {
+        // the same for unknown names, so they can't be told apart
+        Ok(()) => "If this user exists, a link to reset the password is on its way.",
+        Err(Error::TooManyRequests { .. }) => "Too many attempts, please try again later.",
+        Err(e) => {
+            tracing::error!("Failed to request a password reset: {:?}", e);
+            "The password could not be reset, please try again later."
+        }
+    };
+    PasswordForgotTemplate {
+        message: Some(message),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "password_reset.html")]
+struct PasswordResetTemplate {
+    message: Option<&'static str>,
+    token: String,
+    min_length: usize,
+    done: bool,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetQuery {
+    token: String,
+}
+
+pub async fn password_reset_form(Query(query): Query<PasswordResetQuery>) -> impl IntoResponse {
+    PasswordResetTemplate {
+        message: None,
+        token: query.token,
+        min_length: MIN_PASSWORD_LENGTH,
+        done: false,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetInput {
+    token: String,
+    new_password: String,
+    confirm_password: String,
+}
+
+pub async fn handle_password_reset(
+    client_ip: ClientIp,
+    extract::Form(input): extract::Form<PasswordResetInput>,
+) -> impl IntoResponse {
+    let (message, done) = if input.new_password != input.confirm_password {
+        ("The new passwords don't match.", false)
+    } else {
+        let payload = ResetPassword {
+            token: input.token.clone(),
+            new_password: input.new_password,
+        };
+        match client::reset_password(payload, Some(client_ip.to_headers())).await {
+            Ok(_) => ("Your password was reset.", true),
+            Err(Error::BadRequest) => ("The new password is too short.", false),
+            Err(Error::Unauthorized) => (
+                "This link is invalid or has expired, please request a new one.",
+                false,
+            ),
+            Err(Error::TooManyRequests { .. }) => {
+                ("Too many attempts, please try again later.", false)
+            }
+            Err(e) => {
+                tracing::error!("Failed to reset the password: {:?}", e);
+                (
+                    "Your password could not be reset, please try again later.",
+                    false,
+                )
+            }
+        }
+    };
+    PasswordResetTemplate {
+        message: Some(message),
+        token: input.token,
+        min_length: MIN_PASSWORD_LENGTH,
+        done,
+    }
+}
+
 pub async fn perm(method: Method, auth: AuthSessionType) -> String {
     let current_user = auth.current_user.clone().unwrap_or_default();
 
     //lets check permissions only and not worry about if they are anon or not
     if !Auth::<User, Option<Uuid>, NullPool>::build([Method::GET], false)
         .requires(Rights::any([
-            Rights::permission("Category::View"),
-            Rights::permission("Admin::View"),
+            Rights::permission(permission::CATEGORY_VIEW),
+            Rights::permission(permission::ADMIN_VIEW),
         ]))
         .validate(&current_user, &method, None)
         .await
@@ -129,9 +304,11 @@ pub async fn perm(method: Method, auth: AuthSessionType) -> String {
         );
     }
 
+    let mut permissions: Vec<_> = current_user.permissions.iter().collect();
+    permissions.sort();
     format!(
-        "User id {:?} and name {:?} has Permissions needed. Here are the Users permissions: {:?}",
-        current_user.id, current_user.username, current_user.permissions
+        "User id {:?} and name {:?} has Permissions needed. Here are the Users roles: {:?} and permissions: {:?}",
+        current_user.id, current_user.username, current_user.roles, permissions
     )
 }
 
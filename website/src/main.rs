@@ -5,12 +5,13 @@
 //! ```
 mod app_state;
 mod auth;
-mod auth0;
 mod handlers;
+mod oidc;
 mod protected_routes;
 mod proxy_routes;
 mod public_routes;
 mod router;
+mod sessions;
 
 use shared::startup::{create_server, server_setup};
 use std::net::SocketAddr;
@@ -25,7 +26,7 @@ async fn main() {
     let addr = SocketAddr::from(([0, 0, 0, 0], 8000));
     tracing::info!("listening on {}", addr);
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
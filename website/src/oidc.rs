@@ -0,0 +1,1008 @@
+// fn get_routes() -> Vec<rocket::Route> {
+//    routes![
+//        login,
+//        logged_in,
+//        auth0_redirect,
+//        auth0_callback,
+//        home,
+//        home_redirect,
+//        static_files
+//    ]
+//}
+
+use crate::{
+    app_state,
+    auth::{self, unix_now, AuthSessionType, SessionUserAuthData, USER_AUTH_DATA_KEY},
+    handlers,
+    sessions::SessionType,
+};
+use askama::Template;
+use axum::{
+    debug_handler,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use shared::{
+    client,
+    error::{self, AuthError},
+    jwks::JwksValidator,
+    jwt::Claims,
+    schema::LinkIdentity,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+/// Session key of the [`AuthorizationRequest`] waiting for its callback.
+const AUTHORIZATION_REQUEST_KEY: &str = "oidc_authorization_request";
+/// How long a user has to finish logging in at the identity provider.
+const AUTHORIZATION_REQUEST_TTL: Duration = Duration::from_secs(600);
+const DEFAULT_SCOPES: &str = "openid email profile";
+
+/// Either `code` and `state`, or `error` if the user didn't log in.
+#[derive(Deserialize)]
+pub struct CallbackSchema {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// Form sent to the provider's token endpoint.
+#[derive(Serialize)]
+struct TokenRequest<'a> {
+    grant_type: &'static str,
+    client_id: &'a str,
+    client_secret: &'a str,
+    code: &'a str,
+    code_verifier: &'a str,
+    redirect_uri: &'a str,
+}
+
+#[derive(Deserialize)]
+pub struct TokenResponse {
+    pub id_token: String,
+}
+
+/// Helper to create a random string `len` chars long.
+pub fn random_string(len: usize) -> String {
+    use rand::distributions::{Alphanumeric, DistString};
+    Alphanumeric.sample_string(&mut rand::thread_rng(), len)
+}
+
+/// The `state`, `nonce` and PKCE code verifier of a login started by
+/// [`login_redirect`], kept in the session until the callback uses them.
+#[derive(Debug, Serialize, Deserialize)]
+struct AuthorizationRequest {
+    provider: String,
+    state: String,
+    nonce: String,
+    code_verifier: String,
+    expires_at: u64,
+}
+
+impl AuthorizationRequest {
+    fn new(provider: &str) -> Self {
+        Self {
+            provider: provider.to_string(),
+            state: random_string(30),
+            nonce: random_string(30),
+            // RFC 7636 asks for 43 to 128 characters
+            code_verifier: random_string(64),
+            expires_at: unix_now() + AUTHORIZATION_REQUEST_TTL.as_secs(),
+        }
+    }
+
+    /// The S256 PKCE challenge for our code verifier.
+    fn code_challenge(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(self.code_verifier.as_bytes()))
+    }
+
+    /// Checks the callback belongs to this request.
+    fn verify(&self, state: &str, now: u64) -> Result<(), LoginError> {
+        if now >= self.expires_at {
+            return Err(LoginError::Expired);
+        }
+        if state != self.state {
+            return Err(LoginError::StateMismatch);
+        }
+        Ok(())
+    }
+}
+
+/// Why a login was rejected.
+#[derive(Debug)]
+pub enum LoginError {
+    UnknownProvider(String),
+    /// The identity provider redirected back with an `error`.
+    Denied(String),
+    /// No login in progress, or its state was used already.
+    UnknownRequest,
+    Expired,
+    StateMismatch,
+    /// Discovery or the token exchange failed.
+    ProviderRequest(error::Error),
+    InvalidIdToken(AuthError),
+    /// The ID token was issued for another login.
+    NonceMismatch,
+    /// The identity belongs to another user than the one logged in.
+    IdentityInUse,
+    /// api-server failed to look up the identity's user.
+    UserLookup(error::Error),
+}
+
+impl LoginError {
+    fn message(&self) -> &str {
+        match self {
+            LoginError::UnknownProvider(_) => "This login provider is not configured.",
+            LoginError::Denied(_) => "The identity provider did not log you in.",
+            LoginError::UnknownRequest | LoginError::StateMismatch => {
+                "This login link is not valid. Please start the login again."
+            }
+            LoginError::Expired => "This login took too long. Please start the login again.",
+            LoginError::ProviderRequest(_)
+            | LoginError::InvalidIdToken(_)
+            | LoginError::NonceMismatch => {
+                "We could not verify your login with the identity provider."
+            }
+            LoginError::IdentityInUse => {
+                "This login is already linked to another account. Log out and try again."
+            }
+            LoginError::UserLookup(_) => "Something went wrong. Please try again later.",
+        }
+    }
+}
+
+impl std::fmt::Display for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoginError::UnknownProvider(name) => write!(f, "unknown provider {}", name),
+            LoginError::Denied(error) => write!(f, "identity provider error {}", error),
+            LoginError::UnknownRequest => write!(f, "no login in progress"),
+            LoginError::Expired => write!(f, "login expired"),
+            LoginError::StateMismatch => write!(f, "state does not match"),
+            LoginError::ProviderRequest(e) => write!(f, "request to provider failed: {}", e),
+            LoginError::InvalidIdToken(e) => write!(f, "invalid ID token: {}", e),
+            LoginError::NonceMismatch => write!(f, "ID token nonce does not match"),
+            LoginError::IdentityInUse => write!(f, "identity is linked to another user"),
+            LoginError::UserLookup(e) => write!(f, "user lookup failed: {}", e),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "login_error.html")]
+struct LoginErrorTemplate<'a> {
+    message: &'a str,
+}
+
+impl IntoResponse for LoginError {
+    fn into_response(self) -> Response {
+        tracing::warn!("Login rejected: {}", self);
+        let status = match self {
+            LoginError::UnknownProvider(_) => StatusCode::NOT_FOUND,
+            LoginError::ProviderRequest(_) => StatusCode::BAD_GATEWAY,
+            LoginError::IdentityInUse => StatusCode::CONFLICT,
+            LoginError::UserLookup(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        let template = LoginErrorTemplate {
+            message: self.message(),
+        };
+        (status, template).into_response()
+    }
+}
+
+/// The parts of a provider's `/.well-known/openid-configuration` we use.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    pub end_session_endpoint: Option<String>,
+}
+
+/// Configuration of one OpenID Connect provider, including the client
+/// secret, which must be kept private.
+#[derive(Debug, Clone)]
+pub struct ProviderConfig {
+    /// Identifies the provider in URLs, e.g. `/login/keycloak`.
+    pub name: String,
+    pub display_name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Secret<String>,
+    pub scopes: String,
+    /// Auth0 only issues JWT access tokens when asked for an API audience.
+    pub audience: Option<String>,
+}
+
+impl ProviderConfig {
+    /// Reads `OIDC_<NAME>_ISSUER`, `OIDC_<NAME>_CLIENT_ID`,
+    /// `OIDC_<NAME>_CLIENT_SECRET` and the optional `OIDC_<NAME>_DISPLAY_NAME`,
+    /// `OIDC_<NAME>_SCOPES` and `OIDC_<NAME>_AUDIENCE`.
+    pub fn from_env(name: &str) -> Self {
+        let var = |key: &str| {
+            let var = format!("OIDC_{}_{}", name.to_uppercase().replace('-', "_"), key);
+            std::env::var(&var).ok()
+        };
+        let required = |key: &str| {
+            var(key).unwrap_or_else(|| panic!("OIDC_{}_{} must be set", name.to_uppercase(), key))
+        };
+        Self {
+            name: name.to_string(),
+            display_name: var("DISPLAY_NAME").unwrap_or_else(|| name.to_string()),
+            issuer: required("ISSUER"),
+            client_id: required("CLIENT_ID"),
+            client_secret: Secret::new(required("CLIENT_SECRET")),
+            scopes: var("SCOPES").unwrap_or_else(|| DEFAULT_SCOPES.to_string()),
+            audience: var("AUDIENCE"),
+        }
+    }
+}
+
+struct Discovered {
+    metadata: ProviderMetadata,
+    id_tokens: JwksValidator,
+}
+
+/// An OpenID Connect provider, discovered from its issuer on first use.
+pub struct Provider {
+    pub config: ProviderConfig,
+    http_client: reqwest::Client,
+    discovered: OnceCell<Discovered>,
+}
+
+impl std::fmt::Debug for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Provider")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Provider {
+    pub fn new(config: ProviderConfig) -> Self {
+        Self {
+            config,
+            http_client: reqwest::Client::new(),
+            discovered: OnceCell::new(),
+        }
+    }
+
+    async fn discovered(&self) -> Result<&Discovered, error::Error> {
+        self.discovered
+            .get_or_try_init(|| async {
+                let metadata = self.discover().await?;
+                // the ID token's audience is our client id
+                let id_tokens = JwksValidator::new(
+                    &metadata.jwks_uri,
+                    &metadata.issuer,
+                    &self.config.client_id,
+                );
+                Ok(Discovered {
+                    metadata,
+                    id_tokens,
+                })
+            })
+            .await
+    }
+
+    #[tracing::instrument(skip(self), fields(issuer = %self.config.issuer))]
+    async fn discover(&self) -> Result<ProviderMetadata, error::Error> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = self
+            .http_client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        // OpenID Connect Discovery 1.0, section 4.3
+        if metadata.issuer != self.config.issuer {
+            return Err(anyhow::anyhow!(
+                "discovered issuer {} does not match the configured {}",
+                metadata.issuer,
+                self.config.issuer
+            )
+            .into());
+        }
+        Ok(metadata)
+    }
+
+    pub async fn metadata(&self) -> Result<&ProviderMetadata, error::Error> {
+        Ok(&self.discovered().await?.metadata)
+    }
+
+    async fn authorize_url(
+        &self,
+        redirect_uri: &str,
+        request: &AuthorizationRequest,
+    ) -> Result<Url, error::Error> {
+        let endpoint = &self.metadata().await?.authorization_endpoint;
+        let code_challenge = request.code_challenge();
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", self.config.client_id.as_str()),
+            ("redirect_uri", redirect_uri),
+            ("scope", self.config.scopes.as_str()),
+            ("state", request.state.as_str()),
+            ("nonce", request.nonce.as_str()),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ];
+        if let Some(audience) = &self.config.audience {
+            params.push(("audience", audience));
+        }
+        Url::parse_with_params(endpoint, params).map_err(|e| anyhow::Error::from(e).into())
+    }
+
+    async fn exchange_code(
+        &self,
+        redirect_uri: &str,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<TokenResponse, error::Error> {
+        let endpoint = &self.metadata().await?.token_endpoint;
+        let tr = TokenRequest {
+            grant_type: "authorization_code",
+            client_id: &self.config.client_id,
+            client_secret: self.config.client_secret.expose_secret(),
+            code,
+            code_verifier,
+            redirect_uri,
+        };
+        let resp = self
+            .http_client
+            .post(endpoint)
+            .form(&tr)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(resp)
+    }
+
+    async fn validate_id_token(&self, id_token: &str, nonce: &str) -> Result<Claims, LoginError> {
+        let discovered = self
+            .discovered()
+            .await
+            .map_err(LoginError::ProviderRequest)?;
+        let claims = discovered
+            .id_tokens
+            .validate_token(id_token)
+            .await
+            .map_err(|e| match e {
+                AuthError::KeysUnavailable => LoginError::ProviderRequest(e.into()),
+                e => LoginError::InvalidIdToken(e),
+            })?;
+        if claims.extra.get("nonce").and_then(|n| n.as_str()) != Some(nonce) {
+            return Err(LoginError::NonceMismatch);
+        }
+        Ok(claims)
+    }
+}
+
+/// The configured providers, in the order they are shown on the login page.
+#[derive(Debug)]
+pub struct Providers {
+    redirect_uri: String,
+    /// Where providers send users after logging them out. Without it
+    /// `/logout` only ends the website session.
+    post_logout_redirect_uri: Option<String>,
+    providers: Vec<Provider>,
+}
+
+impl Providers {
+    pub fn new(redirect_uri: impl Into<String>, providers: Vec<ProviderConfig>) -> Self {
+        Self {
+            redirect_uri: redirect_uri.into(),
+            post_logout_redirect_uri: None,
+            providers: providers.into_iter().map(Provider::new).collect(),
+        }
+    }
+
+    pub fn with_post_logout_redirect_uri(mut self, uri: impl Into<String>) -> Self {
+        self.post_logout_redirect_uri = Some(uri.into());
+        self
+    }
+
+    /// Reads `OIDC_REDIRECT_URI`, the optional `OIDC_POST_LOGOUT_REDIRECT_URI`
+    /// and the comma separated provider names in `OIDC_PROVIDERS`, see
+    /// [`ProviderConfig::from_env`].
+    pub fn from_env() -> Self {
+        let names = std::env::var("OIDC_PROVIDERS").unwrap_or_default();
+        let configs: Vec<_> = names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(ProviderConfig::from_env)
+            .collect();
+        let redirect_uri = match configs.is_empty() {
+            true => std::env::var("OIDC_REDIRECT_URI").unwrap_or_default(),
+            false => std::env::var("OIDC_REDIRECT_URI").expect("OIDC_REDIRECT_URI must be set"),
+        };
+        let providers = Self::new(redirect_uri, configs);
+        match std::env::var("OIDC_POST_LOGOUT_REDIRECT_URI") {
+            Ok(uri) => providers.with_post_logout_redirect_uri(uri),
+            Err(_) => providers,
+        }
+    }
+
+    /// The provider's end-session endpoint (OpenID Connect RP-Initiated
+    /// Logout), if it has one and we are configured to use it.
+    async fn end_session_url(&self, auth_data: &SessionUserAuthData) -> Option<Url> {
+        let post_logout_redirect_uri = self.post_logout_redirect_uri.as_ref()?;
+        let provider = self.get(&auth_data.provider)?;
+        let metadata = match provider.metadata().await {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                tracing::warn!("Skipping logout at {}: {}", provider.config.name, e);
+                return None;
+            }
+        };
+        let params = [
+            ("id_token_hint", auth_data.raw_jwt.as_str()),
+            ("client_id", provider.config.client_id.as_str()),
+            ("post_logout_redirect_uri", post_logout_redirect_uri),
+        ];
+        Url::parse_with_params(metadata.end_session_endpoint.as_ref()?, params).ok()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Provider> {
+        self.providers.iter().find(|p| p.config.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Provider> {
+        self.providers.iter()
+    }
+}
+
+pub async fn router() -> Router {
+    let oidc = Router::new()
+        .route("/auth0_login", get(handlers::styles))
+        .route("/loggedin", get(handlers::loggedin))
+        .route("/login/:provider", get(login_redirect))
+        .route("/callback", get(callback))
+        // not GET, so other sites can't log users out with a link or image
+        .route("/logout", post(logout))
+        .with_state(app_state::create_app_state().await);
+    oidc
+}
+
+#[debug_handler]
+pub async fn login_redirect(
+    Path(provider): Path<String>,
+    session: SessionType,
+    State(data): State<Arc<app_state::AppState>>,
+) -> Result<Redirect, LoginError> {
+    let provider = data
+        .oidc
+        .get(&provider)
+        .ok_or(LoginError::UnknownProvider(provider))?;
+    // replaces any login still in progress in this session
+    let request = AuthorizationRequest::new(&provider.config.name);
+    let uri = provider
+        .authorize_url(&data.oidc.redirect_uri, &request)
+        .await
+        .map_err(LoginError::ProviderRequest)?;
+    session.set(AUTHORIZATION_REQUEST_KEY, request);
+    Ok(Redirect::to(uri.as_str()))
+}
+
+#[debug_handler]
+pub async fn callback(
+    Query(callback): Query<CallbackSchema>,
+    session: SessionType,
+    auth: AuthSessionType,
+    State(data): State<Arc<app_state::AppState>>,
+) -> Result<Redirect, LoginError> {
+    // single use, whether or not the callback checks out
+    let request: Option<AuthorizationRequest> = session.get_remove(AUTHORIZATION_REQUEST_KEY);
+    if let Some(error) = callback.error {
+        let description = callback.error_description.unwrap_or_default();
+        return Err(LoginError::Denied(format!("{}: {}", error, description)));
+    }
+    let (Some(code), Some(state), Some(request)) = (callback.code, callback.state, request) else {
+        return Err(LoginError::UnknownRequest);
+    };
+    request.verify(&state, unix_now())?;
+    let provider = data
+        .oidc
+        .get(&request.provider)
+        .ok_or(LoginError::UnknownRequest)?;
+
+    let resp = provider
+        .exchange_code(&data.oidc.redirect_uri, &code, &request.code_verifier)
+        .await
+        .map_err(LoginError::ProviderRequest)?;
+    let claims = provider
+        .validate_id_token(&resp.id_token, &request.nonce)
+        .await?;
+
+    let str_claim = |name: &str| {
+        let value = claims.extra.get(name)?.as_str()?;
+        Some(value.to_string())
+    };
+    // a logged in user gets the identity added to their account
+    let current_user = auth.current_user.as_ref().filter(|user| !user.anonymous);
+    let identity = LinkIdentity {
+        provider: provider.config.name.clone(),
+        subject: claims.sub.clone(),
+        email: str_claim("email"),
+        name: str_claim("preferred_username").or_else(|| str_claim("name")),
+        user_id: current_user.map(|user| user.id),
+    };
+    let user = client::link_identity(identity, None)
+        .await
+        .map_err(|e| match e {
+            error::Error::Conflict(_) => LoginError::IdentityInUse,
+            e => LoginError::UserLookup(e),
+        })?;
+    auth::start_session(&auth, &session, user.id);
+
+    session.set(
+        USER_AUTH_DATA_KEY,
+        SessionUserAuthData {
+            provider: provider.config.name.clone(),
+            sub: claims.sub,
+            exp: claims.exp as i64,
+            raw_jwt: resp.id_token,
+        },
+    );
+    Ok(Redirect::to("/loggedin"))
+}
+
+/// Ends the website session and, for OpenID Connect logins, the session at
+/// the provider if [`Providers::end_session_url`] allows.
+pub async fn logout(
+    session: SessionType,
+    auth: AuthSessionType,
+    State(data): State<Arc<app_state::AppState>>,
+) -> Redirect {
+    let auth_data: Option<SessionUserAuthData> = session.get(USER_AUTH_DATA_KEY);
+    auth::end_session(&auth, &session);
+    let end_session_url = match auth_data {
+        Some(auth_data) => data.oidc.end_session_url(&auth_data).await,
+        None => None,
+    };
+    match end_session_url {
+        Some(url) => Redirect::to(url.as_str()),
+        None => Redirect::to("/login"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{auth, oidc::*, sessions};
+    use axum::{
+        body::{Body, HttpBody},
+        http::{Method, Request},
+        middleware,
+    };
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+    use openssl::rsa::Rsa;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn config(issuer: &str) -> ProviderConfig {
+        ProviderConfig {
+            name: "mock".to_string(),
+            display_name: "Mock IdP".to_string(),
+            issuer: issuer.to_string(),
+            client_id: "website".to_string(),
+            client_secret: Secret::new("secret".to_string()),
+            scopes: DEFAULT_SCOPES.to_string(),
+            audience: None,
+        }
+    }
+
+    async fn provider(discovered_issuer: Option<&str>) -> (MockServer, Provider) {
+        let server = MockServer::start().await;
+        let issuer = format!("{}/realms/dev", server.uri());
+        Mock::given(method("GET"))
+            .and(path("/realms/dev/.well-known/openid-configuration"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "issuer": discovered_issuer.unwrap_or(&issuer),
+                "authorization_endpoint": format!("{}/auth?kc_idp_hint=", issuer),
+                "token_endpoint": format!("{}/token", issuer),
+                "jwks_uri": format!("{}/certs", issuer),
+                "end_session_endpoint": format!("{}/logout", issuer),
+                "response_types_supported": ["code"],
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        (server, Provider::new(config(&issuer)))
+    }
+
+    #[test]
+    fn test_code_challenge() {
+        // from RFC 7636, appendix B
+        let mut request = AuthorizationRequest::new("mock");
+        request.code_verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".to_string();
+        assert_eq!(
+            request.code_challenge(),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn test_verify_authorization_request() {
+        let now = unix_now();
+        let request = AuthorizationRequest::new("mock");
+        assert!(request.verify(&request.state, now).is_ok());
+
+        let res = AuthorizationRequest::new("mock").verify(&request.state, now);
+        assert!(matches!(res, Err(LoginError::StateMismatch)));
+
+        let later = now + AUTHORIZATION_REQUEST_TTL.as_secs();
+        let res = request.verify(&request.state, later);
+        assert!(matches!(res, Err(LoginError::Expired)));
+    }
+
+    #[tokio::test]
+    async fn test_authorize_url_from_discovery() {
+        let (server, provider) = provider(None).await;
+        let request = AuthorizationRequest::new("mock");
+
+        let url = provider
+            .authorize_url("http://localhost:8000/callback", &request)
+            .await
+            .unwrap();
+        assert_eq!(url.path(), "/realms/dev/auth");
+        let params: std::collections::HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["kc_idp_hint"], "");
+        assert_eq!(params["client_id"], "website");
+        assert_eq!(params["redirect_uri"], "http://localhost:8000/callback");
+        assert_eq!(params["scope"], "openid email profile");
+        assert_eq!(params["state"], request.state);
+        assert_eq!(params["nonce"], request.nonce);
+        assert_eq!(params["code_challenge"], request.code_challenge());
+        assert!(!params.contains_key("audience"));
+
+        // discovery is cached
+        let token_endpoint = &provider.metadata().await.unwrap().token_endpoint;
+        assert_eq!(
+            token_endpoint,
+            &format!("{}/realms/dev/token", server.uri())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_issuer_mismatch() {
+        let (_server, provider) = provider(Some("https://evil.example/")).await;
+        assert!(provider.metadata().await.is_err());
+    }
+
+    struct SigningKey {
+        encoding: EncodingKey,
+        jwk: Value,
+    }
+
+    fn signing_key() -> SigningKey {
+        let rsa = Rsa::generate(2048).unwrap();
+        SigningKey {
+            encoding: EncodingKey::from_rsa_pem(&rsa.private_key_to_pem().unwrap()).unwrap(),
+            jwk: json!({
+                "kty": "RSA",
+                "kid": "key-1",
+                "n": URL_SAFE_NO_PAD.encode(rsa.n().to_vec()),
+                "e": URL_SAFE_NO_PAD.encode(rsa.e().to_vec()),
+            }),
+        }
+    }
+
+    const USER_ID: &str = "36f9424f-f929-4c78-a28f-6f6c9fcc93b4";
+
+    /// Drives `login_redirect`, `callback` and `logout` against a mock
+    /// provider, keeping the session cookies like a browser would.
+    struct LoginFlow {
+        server: MockServer,
+        issuer: String,
+        app: Router,
+        cookies: HashMap<String, String>,
+    }
+
+    impl LoginFlow {
+        async fn new(key: &SigningKey) -> Self {
+            let (server, provider) = provider(None).await;
+            let issuer = provider.config.issuer.clone();
+            Mock::given(method("GET"))
+                .and(path("/realms/dev/certs"))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_json(json!({ "keys": [key.jwk] })),
+                )
+                .mount(&server)
+                .await;
+            let state = Arc::new(app_state::AppState {
+                handlebars: handlebars::Handlebars::new(),
+                oidc: Providers {
+                    redirect_uri: "http://localhost:8000/callback".to_string(),
+                    post_logout_redirect_uri: Some("http://localhost:8000/login".to_string()),
+                    providers: vec![provider],
+                },
+            });
+            let timeouts = auth::SessionTimeouts {
+                idle: Duration::from_secs(1800),
+            };
+            let session_store = sessions::create_store(None, timeouts).await;
+            let (auth_session_layer, session_layer) = auth::make_auth_session_layer(session_store);
+            let app = Router::new()
+                .route("/login/:provider", get(login_redirect))
+                .route("/callback", get(callback))
+                .route("/logout", post(logout))
+                .route("/me", get(me))
+                .with_state(state)
+                .layer(middleware::from_fn_with_state(
+                    timeouts,
+                    auth::session_expiry,
+                ))
+                .layer(auth_session_layer)
+                .layer(session_layer);
+            Self {
+                server,
+                issuer,
+                app,
+                cookies: HashMap::new(),
+            }
+        }
+
+        async fn get(&mut self, uri: &str) -> Response {
+            self.request(Method::GET, uri).await
+        }
+
+        async fn post(&mut self, uri: &str) -> Response {
+            self.request(Method::POST, uri).await
+        }
+
+        async fn request(&mut self, method: Method, uri: &str) -> Response {
+            let cookie = self
+                .cookies
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join("; ");
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Cookie", cookie)
+                .body(Body::empty())
+                .unwrap();
+            let res = self.app.clone().oneshot(request).await.unwrap();
+            for set_cookie in res.headers().get_all("Set-Cookie") {
+                let cookie = set_cookie.to_str().unwrap().split(';').next().unwrap();
+                let (name, value) = cookie.split_once('=').unwrap();
+                self.cookies.insert(name.to_string(), value.to_string());
+            }
+            res
+        }
+
+        fn id_token_claims(&self, nonce: &str) -> Value {
+            json!({
+                "sub": "mock-user",
+                "iss": self.issuer,
+                "aud": "website",
+                "exp": get_current_timestamp() + 300,
+                "nonce": nonce,
+                "email": "user@example.com",
+            })
+        }
+
+        /// Logs in with an ID token built from the default claims, returning
+        /// the callback's response.
+        async fn login(
+            &mut self,
+            key: &SigningKey,
+            edit_claims: impl FnOnce(&mut Value),
+        ) -> Response {
+            let res = self.get("/login/mock").await;
+            assert_eq!(res.status(), StatusCode::SEE_OTHER);
+            let location = Url::parse(res.headers()["Location"].to_str().unwrap()).unwrap();
+            let params: HashMap<_, _> = location.query_pairs().into_owned().collect();
+
+            let mut claims = self.id_token_claims(&params["nonce"]);
+            edit_claims(&mut claims);
+            let mut header = Header::new(jsonwebtoken::Algorithm::RS256);
+            header.kid = Some("key-1".to_string());
+            let id_token = encode(&header, &claims, &key.encoding).unwrap();
+            let code = random_string(10);
+            Mock::given(method("POST"))
+                .and(path("/realms/dev/token"))
+                .and(body_string_contains(format!("code={}", code)))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "access_token": "opaque",
+                    "token_type": "Bearer",
+                    "id_token": id_token,
+                })))
+                .mount(&self.server)
+                .await;
+
+            let uri = format!("/callback?code={}&state={}", code, params["state"]);
+            self.get(&uri).await
+        }
+    }
+
+    async fn me(auth: AuthSessionType) -> String {
+        match auth.current_user {
+            Some(user) if !user.anonymous => user.id.to_string(),
+            _ => "anonymous".to_string(),
+        }
+    }
+
+    async fn body_string(res: Response) -> String {
+        let mut body = res.into_body();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        String::from_utf8(bytes).unwrap()
+    }
+
+    async fn assert_rejected(res: Response, case: &str) {
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", case);
+        let html = body_string(res).await;
+        assert!(
+            html.contains(LoginError::NonceMismatch.message()),
+            "{}",
+            case
+        );
+    }
+
+    #[tokio::test]
+    async fn test_callback_rejects_invalid_id_tokens() {
+        let key = signing_key();
+        let mut flow = LoginFlow::new(&key).await;
+        let now = get_current_timestamp();
+
+        let res = flow.login(&key, |c| c["exp"] = json!(now - 600)).await;
+        assert_rejected(res, "expired").await;
+        let res = flow.login(&key, |c| c["aud"] = json!("api")).await;
+        assert_rejected(res, "wrong audience").await;
+        let res = flow
+            .login(&key, |c| c["iss"] = json!("https://evil.example/"))
+            .await;
+        assert_rejected(res, "wrong issuer").await;
+        let res = flow.login(&signing_key(), |_| {}).await;
+        assert_rejected(res, "bad signature").await;
+        let res = flow.login(&key, |c| c["nonce"] = json!("replayed")).await;
+        assert_rejected(res, "wrong nonce").await;
+
+        // the login state is single use
+        let res = flow.get("/callback?code=again&state=again").await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    // The only test talking to the api-server, as API_BASE_URL is process wide.
+    #[tokio::test]
+    async fn test_login_and_logout() {
+        let key = signing_key();
+        let mut flow = LoginFlow::new(&key).await;
+        let user = json!({ "data": { "id": USER_ID, "name": "user" } });
+        Mock::given(method("POST"))
+            .and(path("/api/identities"))
+            .and(body_string_contains(r#""subject":"mock-user""#))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&user))
+            .expect(2)
+            .mount(&flow.server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/api/users/{}", USER_ID)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&user))
+            .mount(&flow.server)
+            .await;
+        std::env::set_var("API_BASE_URL", flow.server.uri());
+
+        // the session started by the login redirect, e.g. planted by someone
+        flow.get("/login/mock").await;
+        let planted = flow.cookies.clone();
+        let res = flow.login(&key, |_| {}).await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        assert_eq!(body_string(flow.get("/me").await).await, USER_ID);
+        // is not the one logged in
+        assert_ne!(flow.cookies, planted);
+        let logged_in = std::mem::replace(&mut flow.cookies, planted);
+        assert_eq!(body_string(flow.get("/me").await).await, "anonymous");
+        flow.cookies = logged_in;
+
+        // logs out at the provider too
+        let res = flow.post("/logout").await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let location = Url::parse(res.headers()["Location"].to_str().unwrap()).unwrap();
+        assert_eq!(location.path(), "/realms/dev/logout");
+        let params: HashMap<_, _> = location.query_pairs().into_owned().collect();
+        assert_eq!(params["client_id"], "website");
+        assert_eq!(
+            params["post_logout_redirect_uri"],
+            "http://localhost:8000/login"
+        );
+        assert!(params.contains_key("id_token_hint"));
+        assert_eq!(body_string(flow.get("/me").await).await, "anonymous");
+
+        // without an ID token there is only the local session to end
+        let res = flow.post("/logout").await;
+        assert_eq!(res.headers()["Location"], "/login");
+
+        // the session ends with the ID token, which is still accepted within
+        // the validator's leeway
+        let exp = get_current_timestamp() - 5;
+        let res = flow.login(&key, |c| c["exp"] = json!(exp)).await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let res = flow.get("/me").await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        assert_eq!(res.headers()["Location"], "/login");
+        assert_eq!(body_string(flow.get("/me").await).await, "anonymous");
+    }
+}
+
+/*
+ * example of how to parse the error responses from auth0
+ *
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CommonFields {
+    common_field1: String,
+    common_field2: i32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type")]
+enum MyEnum {
+    #[serde(rename = "schema1")]
+    Schema1 {
+        #[serde(flatten)]
+        common: CommonFields,
+        schema1_field: String,
+    },
+    #[serde(rename = "schema2")]
+    Schema2 {
+        #[serde(flatten)]
+        common: CommonFields,
+        schema2_field: i64,
+    },
+}
+
+fn main() {
+    let json1 = r#"
+        {
+            "type": "schema1",
+            "common_field1": "value1",
+            "common_field2": 42,
+            "schema1_field": "schema1_value"
+        }
+    "#;
+
+    let json2 = r#"
+        {
+            "type": "schema2",
+            "common_field1": "value2",
+            "common_field2": 84,
+            "schema2_field": 123
+        }
+    "#;
+
+    let result1: Result<MyEnum, _> = serde_json::from_str(json1);
+    let result2: Result<MyEnum, _> = serde_json::from_str(json2);
+
+    println!("{:?}", result1);
+    println!("{:?}", result2);
+}
+*/
@@ -4,6 +4,10 @@ use axum::{middleware, routing::get, Router};
 pub async fn router() -> Router {
     let protected = Router::new()
         .route("/greet-protected", get(handlers::greet_protected))
+        .route(
+            "/password",
+            get(handlers::password_change_form).post(handlers::handle_password_change),
+        )
         .route_layer(middleware::from_fn(auth::session_auth))
         .with_state(create_app_state().await);
     protected
@@ -1,6 +1,7 @@
 use axum::{
     debug_handler,
-    extract::{Path, Query, RawQuery, State},
+    extract::{Path, Query, RawQuery, State, TypedHeader},
+    headers::authorization::{Authorization, Bearer},
     http::Method,
     middleware,
     response::IntoResponse,
@@ -13,9 +14,24 @@ use reqwest::Url;
 use reqwest_middleware::ClientWithMiddleware;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use shared::{auth, client, error::Error};
+use shared::{auth, client, error::Error, scope};
 use std::collections::HashMap;
 use std::sync::Arc;
+use uuid::Uuid;
+
+/// api-server routes only the website itself may call, with its service key.
+const NOT_PROXIED: &[&str] = &["identities"];
+
+/// The client whose token `method` on `path` revokes or rotates, if any.
+fn changed_client(method: &Method, path: &str) -> Option<Uuid> {
+    let rest = path.strip_prefix("clients/")?;
+    let id = match (method, rest.split_once('/')) {
+        (&Method::DELETE, None) => rest,
+        (&Method::POST, Some((id, "rotate"))) => id,
+        _ => return None,
+    };
+    id.parse().ok()
+}
 
 #[derive(Debug, Clone)]
 pub struct AppState {
@@ -30,13 +46,17 @@ pub fn router() -> Router {
 
     let proxy = Router::new()
         .route(
-            "/api/:path",
+            "/api/*path",
             get(proxy_handler)
                 .post(proxy_handler)
                 .delete(proxy_handler)
                 .put(proxy_handler),
         )
         .with_state(app_state)
+        .route_layer(middleware::from_fn_with_state(
+            scope::rules(),
+            auth::require_scopes,
+        ))
         .route_layer(middleware::from_fn(auth::token_auth));
     proxy
 }
@@ -49,11 +69,17 @@ async fn proxy_handler(
     query: RawQuery,
     path: Path<String>,
     State(client): State<AppState>,
+    // checked by `token_auth`, and again by the api-server
+    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
     body_: Option<Json<Value>>,
 ) -> Result<impl IntoResponse, Error> {
     let api_base_url = std::env::var("API_BASE_URL").expect("Define API_BASE_URL");
     tracing::debug!("Request method {:?}", method);
     tracing::debug!("Query params {:?}", query);
+    let resource = path.split('/').next().unwrap_or_default();
+    if NOT_PROXIED.contains(&resource) {
+        return Err(Error::NotFound);
+    }
 
     let host_path = format!(
         "{}/api/{}?{}",
@@ -66,23 +92,46 @@ async fn proxy_handler(
         Error::BadRequest
     })?;
 
+    let req = client
+        .client
+        .request(method.clone(), url.clone())
+        .bearer_auth(auth.token());
     let req = match body_ {
-        Some(body) => {
-            client
-                .client
-                .request(method, url.clone())
-                .json::<Value>(&body)
-                .send()
-                .await?
-        }
-        None => client.client.request(method, url.clone()).send().await?,
+        Some(body) => req.json::<Value>(&body).send().await?,
+        None => req.send().await?,
     };
 
     tracing::info!("Req output {:?}", req);
 
-    let status = req.status().clone();
+    let status = req.status();
     let json = req.json::<Value>().await?;
 
-    // TODO proper status
-    Ok(Json(json))
+    // so the old token stops working here now, not once its cache entry
+    // expires
+    if status.is_success() {
+        if let Some(client_id) = changed_client(&method, &path) {
+            auth::token_cache().evict_client(client_id);
+        }
+    }
+
+    Ok((status, Json(json)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proxy_routes::*;
+
+    #[test]
+    fn test_changed_client() {
+        let id = Uuid::new_v4();
+        let revoke = format!("clients/{}", id);
+        let rotate = format!("clients/{}/rotate", id);
+
+        assert_eq!(changed_client(&Method::DELETE, &revoke), Some(id));
+        assert_eq!(changed_client(&Method::POST, &rotate), Some(id));
+        assert_eq!(changed_client(&Method::GET, &revoke), None);
+        assert_eq!(changed_client(&Method::DELETE, &rotate), None);
+        assert_eq!(changed_client(&Method::DELETE, "clients/abc"), None);
+        assert_eq!(changed_client(&Method::DELETE, "accounts/1"), None);
+    }
 }
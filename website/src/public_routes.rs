@@ -8,6 +8,14 @@ pub async fn router() -> Router {
         .route("/styles", get(handlers::styles))
         .route("/greet/:name", get(handlers::greet))
         .route("/login", get(handlers::login).post(handlers::handle_login))
+        .route(
+            "/password/forgot",
+            get(handlers::password_forgot_form).post(handlers::handle_password_forgot),
+        )
+        .route(
+            "/password/reset",
+            get(handlers::password_reset_form).post(handlers::handle_password_reset),
+        )
         .route("/about", get(handlers::about_page))
         .route("/perm", get(handlers::perm))
         .with_state(app_state::create_app_state().await);
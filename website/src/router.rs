@@ -1,16 +1,23 @@
-use crate::{auth, auth0, protected_routes, proxy_routes, public_routes};
-use axum::Router;
+use crate::{auth, oidc, protected_routes, proxy_routes, public_routes, sessions};
+use axum::{middleware, Router};
 
 pub async fn routes() -> Router {
-    let (auth_session_layer, session_layer) = auth::make_auth_session_layer().await;
+    let timeouts = auth::SessionTimeouts::from_env();
+    let session_store = sessions::create_store(sessions::pool_from_env().await, timeouts).await;
+    tokio::spawn(sessions::run_cleanup(session_store.clone()));
+    let (auth_session_layer, session_layer) = auth::make_auth_session_layer(session_store);
 
     Router::new()
         .merge(protected_routes::router().await)
         .merge(public_routes::router().await)
+        .merge(oidc::router().await)
+        .layer(middleware::from_fn_with_state(
+            timeouts,
+            auth::session_expiry,
+        ))
         // include authentication session middleware
         .layer(auth_session_layer)
         // include session storage
-        .merge(auth0::router().await)
         .layer(session_layer)
         // include proxy after the session auth
         .merge(proxy_routes::router())
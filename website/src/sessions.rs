@@ -0,0 +1,83 @@
+//! Where `axum_session` keeps sessions: Postgres (the `sessions` table of
+//! api/migrations), or Redis with the `redis` feature. Both can be shared by
+//! several website replicas.
+
+use axum_session::{Session, SessionConfig, SessionStore};
+use chrono::Duration;
+use std::time::Duration as StdDuration;
+
+use crate::auth::SessionTimeouts;
+
+#[cfg(not(feature = "redis"))]
+pub type SessionPool = axum_session::SessionPgPool;
+#[cfg(feature = "redis")]
+pub type SessionPool = axum_session::SessionRedisPool;
+
+pub type SessionType = Session<SessionPool>;
+
+const TABLE_NAME: &str = "sessions";
+const DEFAULT_CLEANUP_INTERVAL_SECONDS: u64 = 600;
+
+/// Connects to the database in `DATABASE_URL`. Without it sessions are only
+/// kept in memory, which is fine for a single replica in development.
+#[cfg(not(feature = "redis"))]
+pub async fn pool_from_env() -> Option<SessionPool> {
+    let database_url = std::env::var("DATABASE_URL").ok()?;
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(5)
+        .acquire_timeout(StdDuration::from_secs(2))
+        .connect(&database_url)
+        .await
+        .expect("Failed to connect to the session database");
+    Some(pool.into())
+}
+
+/// Connects to the server in `REDIS_URL`. Without it sessions are only kept
+/// in memory, which is fine for a single replica in development.
+#[cfg(feature = "redis")]
+pub async fn pool_from_env() -> Option<SessionPool> {
+    let redis_url = std::env::var("REDIS_URL").ok()?;
+    let client = redis::Client::open(redis_url).expect("REDIS_URL must be a redis URL");
+    Some(redis_pool::RedisPool::from(client).into())
+}
+
+/// Sessions are removed from the store once idle for `timeouts.idle`.
+pub async fn create_store(
+    pool: Option<SessionPool>,
+    timeouts: SessionTimeouts,
+) -> SessionStore<SessionPool> {
+    let idle = Duration::from_std(timeouts.idle).expect("idle timeout is out of range");
+    let mut config = SessionConfig::default()
+        .with_table_name(TABLE_NAME)
+        .with_lifetime(idle);
+    match pool {
+        // Always read sessions from the store, another replica may have
+        // changed them.
+        Some(_) => config = config.with_memory_lifetime(Duration::zero()),
+        None => tracing::warn!("No session store configured, sessions are kept in memory"),
+    }
+    SessionStore::new(pool, config)
+        .await
+        .expect("Failed to set up the session store")
+}
+
+/// Removes expired sessions from the store every
+/// `SESSION_CLEANUP_INTERVAL_SECONDS` (default 600).
+pub async fn run_cleanup(store: SessionStore<SessionPool>) {
+    let interval = std::env::var("SESSION_CLEANUP_INTERVAL_SECONDS")
+        .map(|v| {
+            v.parse()
+                .expect("SESSION_CLEANUP_INTERVAL_SECONDS must be a number")
+        })
+        .unwrap_or(DEFAULT_CLEANUP_INTERVAL_SECONDS);
+    if !store.is_persistent() {
+        return;
+    }
+    tracing::info!("Session cleanup started");
+    loop {
+        tokio::time::sleep(StdDuration::from_secs(interval)).await;
+        if let Err(e) = store.cleanup().await {
+            tracing::error!("Session cleanup failed: {:?}", e);
+        }
+    }
+}